@@ -0,0 +1,6434 @@
+use anyhow::{anyhow, bail, Context, Result};
+use chrono::format::{Item, StrftimeItems};
+use chrono::{
+    DateTime, Datelike, Duration, Months, NaiveDate, NaiveDateTime, TimeZone, Timelike, Utc,
+    Weekday,
+};
+use clap::{Parser, Subcommand};
+use futures::executor::block_on;
+use glob::Pattern;
+use postgres::config::Host;
+use postgres::error::SqlState;
+use postgres::row::Row;
+use postgres::{Client, NoTls};
+use prompts::{confirm::ConfirmPrompt, text::TextPrompt, Prompt};
+use regex::Regex;
+use schemars::JsonSchema;
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+use std::error::Error;
+use std::fmt::{self};
+use std::fs::File;
+use std::io::{IsTerminal, Write};
+use std::path::PathBuf;
+use std::str::FromStr;
+use std::sync::atomic::{AtomicBool, AtomicUsize, Ordering};
+use std::sync::{Mutex, OnceLock};
+use std::time::Instant;
+
+// Set by the SIGINT handler, checked between tables so a DROP is never cut off midway
+static INTERRUPTED: AtomicBool = AtomicBool::new(false);
+
+// Warnings printed so far, a non-zero count fails the run under --strict
+static WARNINGS: AtomicUsize = AtomicUsize::new(0);
+static STRICT: AtomicBool = AtomicBool::new(false);
+
+// Set for reporting modes and read_only configs. QuestDB has no read-only
+// sessions, so every statement that changes data is gated on this instead.
+static READ_ONLY: AtomicBool = AtomicBool::new(false);
+
+// Fleet-wide kill-switch, set during freezes to neutralize retention regardless of config
+const KILL_SWITCH_VAR: &str = "QUESTDB_RETENTION_DISABLE";
+
+fn kill_switch_active() -> bool {
+    std::env::var(KILL_SWITCH_VAR).is_ok_and(|v| v == "1" || v.eq_ignore_ascii_case("true"))
+}
+
+// list, estimate, compare-modes and --export-plan only report, so they always run read-only
+fn is_read_only_session(args: &Args, config: &Config, kill_switch: bool) -> bool {
+    let reporting = args.list
+        || args.export_plan.is_some()
+        || matches!(
+            args.command,
+            Some(Command::CompareModes | Command::Estimate)
+        );
+    reporting || config.read_only || kill_switch
+}
+
+fn check_writable(action: &str) -> Result<()> {
+    if READ_ONLY.load(Ordering::SeqCst) {
+        bail!("refusing to {} in a read-only session", action);
+    }
+    Ok(())
+}
+
+macro_rules! warn {
+    ($($arg:tt)*) => {{
+        WARNINGS.fetch_add(1, Ordering::SeqCst);
+        let prefix = paint("warning:", YELLOW, std::io::stderr().is_terminal());
+        eprintln!("{} {}", prefix, format_args!($($arg)*));
+    }};
+}
+
+#[derive(Debug)]
+enum RetentionPeriodError {
+    InvalidAmount(i64, PartitionBy),
+    InvalidPartitionBy(PartitionBy),
+    UnsupportedPartitionBy(PartitionBy),
+    UnknownPartitionBy(String),
+    NoDesignatedTimestamp(String),
+    UnsupportedVersion(String),
+    InvalidWhereClause(String, String),
+    PartitionInUse(String),
+    InvalidFloor(Duration),
+}
+
+impl Error for RetentionPeriodError {
+    fn source(&self) -> Option<&(dyn Error + 'static)> {
+        None
+    }
+}
+
+impl fmt::Display for RetentionPeriodError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            RetentionPeriodError::UnsupportedPartitionBy(x) => {
+                write!(f, "unsupported PartitionBy {}", x)
+            }
+            RetentionPeriodError::InvalidPartitionBy(x) => write!(f, "invalid PartitionBy {}", x),
+            RetentionPeriodError::InvalidAmount(x, unit) => write!(
+                f,
+                "invalid amount {} for a table partitioned by {}, amount is the number of {}s to keep and must be >= 1",
+                x, unit, unit
+            ),
+            RetentionPeriodError::UnknownPartitionBy(x) => {
+                write!(f, "unknown PartitionBy value: '{}'", x)
+            }
+            RetentionPeriodError::NoDesignatedTimestamp(x) => write!(
+                f,
+                "table '{}' has no designated timestamp, time-based retention cannot be applied",
+                x
+            ),
+            RetentionPeriodError::UnsupportedVersion(x) => {
+                write!(f, "unsupported QuestDB version: '{}'", x)
+            }
+            RetentionPeriodError::InvalidWhereClause(x, reason) => {
+                write!(f, "invalid where_clause '{}': {}", x, reason)
+            }
+            RetentionPeriodError::PartitionInUse(x) => {
+                write!(f, "skipped active partition for table {}", x)
+            }
+            RetentionPeriodError::InvalidFloor(x) => write!(
+                f,
+                "invalid floor of {} days, it reaches back further than a timestamp can",
+                x.num_days()
+            ),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+struct RetentionPeriod {
+    amount: i64,
+    partition_by: PartitionBy,
+}
+
+fn new_retention_period(
+    amount: i64,
+    partition_by: PartitionBy,
+) -> Result<RetentionPeriod, RetentionPeriodError> {
+    if partition_by == PartitionBy::None {
+        return Err(RetentionPeriodError::InvalidPartitionBy(partition_by));
+    }
+
+    if amount <= 0 {
+        return Err(RetentionPeriodError::InvalidAmount(amount, partition_by));
+    }
+
+    Ok(RetentionPeriod {
+        amount,
+        partition_by,
+    })
+}
+
+#[derive(Debug, PartialEq, Clone, Serialize, Deserialize)]
+enum PartitionBy {
+    None,
+    Year,
+    Month,
+    Week,
+    Day,
+    Hour,
+}
+
+impl fmt::Display for PartitionBy {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{:?}", self)
+    }
+}
+
+impl FromStr for PartitionBy {
+    type Err = RetentionPeriodError;
+
+    fn from_str(input: &str) -> Result<PartitionBy, Self::Err> {
+        match input {
+            "NONE" => Ok(PartitionBy::None),
+            "YEAR" => Ok(PartitionBy::Year),
+            "MONTH" => Ok(PartitionBy::Month),
+            "WEEK" => Ok(PartitionBy::Week),
+            "DAY" => Ok(PartitionBy::Day),
+            "HOUR" => Ok(PartitionBy::Hour),
+            _ => Err(RetentionPeriodError::UnknownPartitionBy(input.to_string())),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+struct Table {
+    name: String,
+    partition_by: PartitionBy,
+}
+
+// Column names of the tables() function, which have changed between QuestDB releases
+#[derive(Debug, Clone)]
+struct MetadataColumns {
+    name: &'static str,
+    designated_timestamp: &'static str,
+    partition_by: &'static str,
+}
+
+fn parse_server_version(build: &str) -> Result<(u32, u32), RetentionPeriodError> {
+    let unsupported = || RetentionPeriodError::UnsupportedVersion(build.to_string());
+    let version = build
+        .split("QuestDB ")
+        .nth(1)
+        .and_then(|v| v.split(|c: char| c != '.' && !c.is_ascii_digit()).next())
+        .ok_or_else(unsupported)?;
+
+    let mut parts = version.split('.').map(|p| p.parse::<u32>());
+    match (parts.next(), parts.next()) {
+        (Some(Ok(major)), Some(Ok(minor))) => Ok((major, minor)),
+        _ => Err(unsupported()),
+    }
+}
+
+fn metadata_columns(build: &str) -> Result<MetadataColumns, RetentionPeriodError> {
+    match parse_server_version(build)? {
+        // 7.3 renamed tables().name to table_name
+        (6, _) | (7, 0..=2) => Ok(MetadataColumns {
+            name: "name",
+            designated_timestamp: "designatedTimestamp",
+            partition_by: "partitionBy",
+        }),
+        (7, _) | (8, _) => Ok(MetadataColumns {
+            name: "table_name",
+            designated_timestamp: "designatedTimestamp",
+            partition_by: "partitionBy",
+        }),
+        // newer releases are assumed to keep the latest known layout
+        (major, minor) if major > 8 => {
+            warn!(
+                "QuestDB {}.{} is newer than the releases this tool knows, assuming the 8.x tables() columns",
+                major, minor
+            );
+            Ok(MetadataColumns {
+                name: "table_name",
+                designated_timestamp: "designatedTimestamp",
+                partition_by: "partitionBy",
+            })
+        }
+        _ => Err(RetentionPeriodError::UnsupportedVersion(build.to_string())),
+    }
+}
+
+fn detect_metadata_columns(client: &mut Client) -> Result<MetadataColumns> {
+    let build: String = client
+        .query_one("SELECT build() build", &[])
+        .context("failed to query the QuestDB version")?
+        .get("build");
+    Ok(metadata_columns(&build)?)
+}
+
+// Oldest release known to provide table_partitions(), which nearly every
+// partition, size and cutoff query relies on
+const TABLE_PARTITIONS_MIN_VERSION: &str = "7.1";
+
+// Probes table_partitions() on one existing table, so a server without it fails
+// with a clear message up front instead of with a cryptic SQL error per table
+fn check_table_partitions(client: &mut Client, cols: &MetadataColumns) -> Result<()> {
+    let query = format!("SELECT {} FROM tables() LIMIT 1", cols.name);
+    let probe_table: Option<String> = client
+        .query_opt(&query, &[])
+        .context("failed to list tables")?
+        .map(|r| r.get(cols.name));
+    let table = match probe_table {
+        Some(t) => t,
+        // nothing to query on an empty database
+        None => return Ok(()),
+    };
+    table_partitions(client, &table, "count() n", "").map(|_| ())
+}
+
+// Every table_partitions() query goes through here, so a server without the
+// function fails with the release it needs rather than an unknown function error
+fn table_partitions(
+    client: &mut Client,
+    table: &str,
+    columns: &str,
+    clauses: &str,
+) -> Result<Vec<Row>> {
+    let query = format!(
+        "SELECT {} FROM table_partitions('{}') {}",
+        columns, table, clauses
+    );
+    client.query(query.trim_end(), &[]).map_err(|e| {
+        let unknown = e
+            .as_db_error()
+            .is_some_and(|db| db.message().to_lowercase().contains("unknown function"));
+        if unknown {
+            anyhow!(e).context(format!(
+                "this server does not provide table_partitions(), which retention requires; upgrade to QuestDB {} or later",
+                TABLE_PARTITIONS_MIN_VERSION
+            ))
+        } else {
+            anyhow!(e).context(format!("failed to query table_partitions('{}')", table))
+        }
+    })
+}
+
+// The single row of an aggregate over table_partitions()
+fn table_partitions_row(
+    client: &mut Client,
+    table: &str,
+    columns: &str,
+    clauses: &str,
+) -> Result<Row> {
+    table_partitions(client, table, columns, clauses)?
+        .into_iter()
+        .next()
+        .ok_or_else(|| anyhow!("table_partitions('{}') returned no rows", table))
+}
+
+// What to do about a configured table that does not exist
+#[derive(Debug, Clone, Copy, Default, PartialEq, Serialize, Deserialize, JsonSchema)]
+#[serde(rename_all = "lowercase")]
+enum MissingTable {
+    // stop before anything runs
+    Error,
+    Skip,
+    #[default]
+    Warn,
+}
+
+// Which clock retention is counted back from
+#[derive(Debug, Clone, Copy, Default, PartialEq, Serialize, Deserialize, JsonSchema)]
+#[serde(rename_all = "lowercase")]
+enum Clock {
+    #[default]
+    Client,
+    // the server's now(), so cutoffs agree with the timestamps QuestDB writes
+    Server,
+}
+
+// Added to the client clock, set once at startup with clock: server
+static CLOCK_OFFSET: OnceLock<Duration> = OnceLock::new();
+
+// Skew beyond which the client clock is worth a warning
+const CLOCK_SKEW_WARNING_SECS: i64 = 60;
+
+fn reference_now() -> DateTime<Utc> {
+    Utc::now() + *CLOCK_OFFSET.get_or_init(Duration::zero)
+}
+
+// How far the server's clock is ahead of the client's, measured against the
+// midpoint of the round trip
+fn measure_clock_skew(client: &mut Client) -> Result<Duration, postgres::Error> {
+    let before = Utc::now();
+    let server: NaiveDateTime = client.query_one("SELECT now() now", &[])?.get("now");
+    let after = Utc::now();
+    let midpoint = before + (after - before) / 2;
+    Ok(Utc.from_utc_datetime(&server) - midpoint)
+}
+
+fn set_reference_clock(client: &mut Client, clock: Clock) -> Result<()> {
+    let skew = match (measure_clock_skew(client), clock) {
+        (Ok(skew), _) => skew,
+        (Err(e), Clock::Server) => {
+            return Err(anyhow!(e).context("failed to read the server clock"))
+        }
+        // only needed for the skew warning
+        (Err(..), Clock::Client) => return Ok(()),
+    };
+    if clock == Clock::Server {
+        CLOCK_OFFSET.set(skew).expect("clock offset is set once");
+    } else if skew.num_seconds().abs() >= CLOCK_SKEW_WARNING_SECS {
+        warn!(
+            "the server clock is {}s {} the client's, set clock: server to count retention from the server's time",
+            skew.num_seconds().abs(),
+            if skew > Duration::zero() { "ahead of" } else { "behind" }
+        );
+    }
+    Ok(())
+}
+
+fn row_to_table(r: &Row, cols: &MetadataColumns) -> Result<Table, RetentionPeriodError> {
+    match PartitionBy::from_str(r.get(cols.partition_by)) {
+        Ok(p) => Ok(Table {
+            name: r.get(cols.name),
+            partition_by: p,
+        }),
+        Err(e) => Err(e),
+    }
+}
+
+// Returns the designated timestamp, or else the first of `fallbacks` that is a
+// TIMESTAMP column of the table, or None when neither exists
+fn get_timestamp_col(
+    client: &mut Client,
+    cols: &MetadataColumns,
+    table: &str,
+    fallbacks: &[String],
+) -> Result<Option<String>, postgres::Error> {
+    let query = format!(
+        "SELECT {} FROM tables() WHERE {}='{}'",
+        cols.designated_timestamp, cols.name, table
+    );
+    let col: Option<String> = client
+        .query_one(&query, &[])?
+        .get(cols.designated_timestamp);
+    if let Some(col) = col.filter(|c| !c.is_empty()) {
+        return Ok(Some(col));
+    }
+    if fallbacks.is_empty() {
+        return Ok(None);
+    }
+
+    let columns = get_table_columns(client, table)?;
+    for fallback in fallbacks {
+        if let Some((name, _)) = columns.iter().find(|(name, ty)| {
+            name.eq_ignore_ascii_case(fallback) && ty.eq_ignore_ascii_case("TIMESTAMP")
+        }) {
+            warn!(
+                "{} has no designated timestamp, using fallback column {}",
+                table, name
+            );
+            return Ok(Some(name.clone()));
+        }
+    }
+    Ok(None)
+}
+
+// Name and type of each column of a table
+fn get_table_columns(
+    client: &mut Client,
+    table: &str,
+) -> Result<Vec<(String, String)>, postgres::Error> {
+    let query = format!("SELECT \"column\", type FROM table_columns('{}')", table);
+    let rows = client.query(&query, &[])?;
+    Ok(rows
+        .iter()
+        .map(|r| (r.get::<_, String>("column"), r.get::<_, String>("type")))
+        .collect())
+}
+
+// Symbol columns and whether each is indexed, None on servers whose
+// table_columns() does not report indexes
+fn get_symbol_columns(client: &mut Client, table: &str) -> Option<Vec<(String, bool)>> {
+    let query = format!(
+        "SELECT \"column\", indexed FROM table_columns('{}') WHERE type = 'SYMBOL'",
+        table
+    );
+    let rows = client.query(&query, &[]).ok()?;
+    rows.iter()
+        .map(|r| Some((r.try_get("column").ok()?, r.try_get("indexed").ok()?)))
+        .collect()
+}
+
+// What of a table's index and symbol storage is part of the estimate. QuestDB
+// does not report their sizes separately: index files live in each partition
+// and are counted in diskSize, symbol dictionaries are table-wide and stay.
+fn describe_symbol_storage(columns: &[(String, bool)]) -> Option<String> {
+    if columns.is_empty() {
+        return None;
+    }
+    let indexed: Vec<&str> = columns
+        .iter()
+        .filter(|(_, i)| *i)
+        .map(|(c, _)| c.as_str())
+        .collect();
+    let mut parts = Vec::new();
+    if !indexed.is_empty() {
+        parts.push(format!(
+            "index files of {} are included",
+            indexed.join(", ")
+        ));
+    }
+    parts.push(format!(
+        "symbol dictionaries of {} columns are table-wide and not freed",
+        columns.len()
+    ));
+    Some(parts.join(", "))
+}
+
+fn get_table_size(client: &mut Client, table: &str) -> Result<i64> {
+    let total: Option<i64> =
+        table_partitions_row(client, table, "sum(diskSize) total", "")?.get("total");
+    Ok(total.unwrap_or(0))
+}
+
+// Size of the partitions that lie entirely before the cutoff
+fn get_droppable_size(client: &mut Client, table: &str, cutoff: &DateTime<Utc>) -> Result<i64> {
+    let filter = format!("WHERE maxTimestamp < {}", to_timestamp_sql(cutoff));
+    let total: Option<i64> =
+        table_partitions_row(client, table, "sum(diskSize) total", &filter)?.get("total");
+    Ok(total.unwrap_or(0))
+}
+
+// Partition count, row count and size of the partitions that lie entirely before the cutoff
+fn get_droppable_stats(
+    client: &mut Client,
+    table: &str,
+    cutoff: &DateTime<Utc>,
+) -> Result<(i64, i64, i64)> {
+    let filter = format!("WHERE maxTimestamp < {}", to_timestamp_sql(cutoff));
+    let columns = "count() n, sum(numRows) rows, sum(diskSize) size";
+    let r = table_partitions_row(client, table, columns, &filter)?;
+    let rows: Option<i64> = r.get("rows");
+    let size: Option<i64> = r.get("size");
+    Ok((r.get("n"), rows.unwrap_or(0), size.unwrap_or(0)))
+}
+
+fn count_rows(client: &mut Client, table: &str) -> Result<i64, postgres::Error> {
+    let query = format!("SELECT count() n FROM {}", table);
+    client.query_one(&query, &[]).map(|r| r.get("n"))
+}
+
+fn count_partitions(client: &mut Client, table: &str) -> Result<i64> {
+    Ok(table_partitions_row(client, table, "count() n", "")?.get("n"))
+}
+
+fn count_droppable_partitions(
+    client: &mut Client,
+    table: &str,
+    cutoff: &DateTime<Utc>,
+) -> Result<i64> {
+    let filter = format!("WHERE maxTimestamp < {}", to_timestamp_sql(cutoff));
+    Ok(table_partitions_row(client, table, "count() n", &filter)?.get("n"))
+}
+
+fn list_droppable_partitions(
+    client: &mut Client,
+    table: &str,
+    cutoff: &DateTime<Utc>,
+) -> Result<Vec<String>> {
+    let clauses = format!(
+        "WHERE maxTimestamp < {} ORDER BY minTimestamp",
+        to_timestamp_sql(cutoff)
+    );
+    let rows = table_partitions(client, table, "name", &clauses)?;
+    Ok(rows.iter().map(|r| r.get("name")).collect())
+}
+
+struct PartitionRange {
+    name: String,
+    min: DateTime<Utc>,
+    max: DateTime<Utc>,
+    rows: i64,
+}
+
+// Each partition entirely before the cutoff, oldest first
+fn list_droppable_ranges(
+    client: &mut Client,
+    table: &str,
+    cutoff: &DateTime<Utc>,
+) -> Result<Vec<PartitionRange>> {
+    let filter = format!("WHERE maxTimestamp < {}", to_timestamp_sql(cutoff));
+    query_partition_ranges(client, table, &filter)
+}
+
+// Every partition of a table, oldest first
+fn list_partition_ranges(client: &mut Client, table: &str) -> Result<Vec<PartitionRange>> {
+    query_partition_ranges(client, table, "")
+}
+
+fn query_partition_ranges(
+    client: &mut Client,
+    table: &str,
+    filter: &str,
+) -> Result<Vec<PartitionRange>> {
+    let columns = "name, minTimestamp, maxTimestamp, numRows";
+    let clauses = format!("{} ORDER BY minTimestamp", filter);
+    let rows = table_partitions(client, table, columns, clauses.trim_start())?;
+    Ok(rows
+        .iter()
+        .map(|r| {
+            let min: NaiveDateTime = r.get("minTimestamp");
+            let max: NaiveDateTime = r.get("maxTimestamp");
+            PartitionRange {
+                name: r.get("name"),
+                min: Utc.from_utc_datetime(&min),
+                max: Utc.from_utc_datetime(&max),
+                rows: r.get("numRows"),
+            }
+        })
+        .collect())
+}
+
+fn count_preserved_rows(
+    client: &mut Client,
+    table: &str,
+    timestamp_col: &str,
+    range: (&DateTime<Utc>, &DateTime<Utc>),
+    preserve: &str,
+) -> Result<i64, postgres::Error> {
+    let col = quote_ident(timestamp_col);
+    let query = format!(
+        "SELECT count() n FROM {} WHERE {} >= {} AND {} <= {} AND ({})",
+        table,
+        col,
+        to_timestamp_sql(range.0),
+        col,
+        to_timestamp_sql(range.1),
+        preserve
+    );
+    client.query_one(&query, &[]).map(|r| r.get("n"))
+}
+
+fn format_bytes(bytes: i64) -> String {
+    const UNITS: &[&str] = &["B", "KiB", "MiB", "GiB", "TiB", "PiB"];
+    let mut size = bytes as f64;
+    let mut unit = 0;
+    while size.abs() >= 1024.0 && unit < UNITS.len() - 1 {
+        size /= 1024.0;
+        unit += 1;
+    }
+    if unit == 0 {
+        format!("{} {}", bytes, UNITS[0])
+    } else {
+        format!("{:.1} {}", size, UNITS[unit])
+    }
+}
+
+fn list_partition_starts(client: &mut Client, table: &str) -> Result<Vec<DateTime<Utc>>> {
+    let rows = table_partitions(client, table, "minTimestamp", "ORDER BY minTimestamp")?;
+    Ok(rows
+        .iter()
+        .map(|r| Utc.from_utc_datetime(&r.get::<_, NaiveDateTime>("minTimestamp")))
+        .collect())
+}
+
+fn get_oldest_partition(client: &mut Client, table: &str) -> Result<Option<DateTime<Utc>>> {
+    let oldest: Option<NaiveDateTime> =
+        table_partitions_row(client, table, "min(minTimestamp) oldest", "")?.get("oldest");
+    Ok(oldest.map(|t| Utc.from_utc_datetime(&t)))
+}
+
+fn get_oldest_value(
+    client: &mut Client,
+    table: &str,
+    column: &str,
+) -> Result<Option<DateTime<Utc>>, postgres::Error> {
+    let query = format!("SELECT min({}) oldest FROM {}", quote_ident(column), table);
+    let oldest: Option<NaiveDateTime> = client.query_one(&query, &[])?.get("oldest");
+    Ok(oldest.map(|t| Utc.from_utc_datetime(&t)))
+}
+
+fn get_newest_value(
+    client: &mut Client,
+    table: &str,
+    column: &str,
+) -> Result<Option<DateTime<Utc>>, postgres::Error> {
+    let query = format!("SELECT max({}) newest FROM {}", quote_ident(column), table);
+    let newest: Option<NaiveDateTime> = client.query_one(&query, &[])?.get("newest");
+    Ok(newest.map(|t| Utc.from_utc_datetime(&t)))
+}
+
+// Checks that a configured timestamp_column exists on the table and is a TIMESTAMP
+fn check_timestamp_column(client: &mut Client, table: &str, column: &str) -> Result<String> {
+    let found = get_table_columns(client, table)
+        .with_context(|| format!("failed to list the columns of {}", table))?
+        .into_iter()
+        .find(|(name, _)| name.eq_ignore_ascii_case(column));
+    match found {
+        Some((name, ty)) if ty.eq_ignore_ascii_case("TIMESTAMP") => Ok(name),
+        Some((name, ty)) => bail!(
+            "timestamp_column '{}' of {} has type {}, expected TIMESTAMP",
+            name,
+            table,
+            ty
+        ),
+        None => bail!("timestamp_column '{}' does not exist on {}", column, table),
+    }
+}
+
+fn get_newest_partition_start(client: &mut Client, table: &str) -> Result<Option<DateTime<Utc>>> {
+    let newest: Option<NaiveDateTime> =
+        table_partitions_row(client, table, "max(minTimestamp) newest", "")?.get("newest");
+    Ok(newest.map(|t| Utc.from_utc_datetime(&t)))
+}
+
+fn get_newest_timestamp(client: &mut Client, table: &str) -> Result<Option<DateTime<Utc>>> {
+    let newest: Option<NaiveDateTime> =
+        table_partitions_row(client, table, "max(maxTimestamp) newest", "")?.get("newest");
+    Ok(newest.map(|t| Utc.from_utc_datetime(&t)))
+}
+
+fn get_oldest_timestamp(
+    now: DateTime<Utc>,
+    p: RetentionPeriod,
+) -> Result<DateTime<Utc>, RetentionPeriodError> {
+    // amounts too large for a timestamp are invalid rather than a panic
+    let cutoff = match p.partition_by {
+        PartitionBy::Week => Duration::try_weeks(p.amount).and_then(|d| now.checked_sub_signed(d)),
+        PartitionBy::Day => Duration::try_days(p.amount).and_then(|d| now.checked_sub_signed(d)),
+        PartitionBy::Hour => Duration::try_hours(p.amount).and_then(|d| now.checked_sub_signed(d)),
+        PartitionBy::Month => months_before(now, p.amount),
+        PartitionBy::None => {
+            return Err(RetentionPeriodError::UnsupportedPartitionBy(p.partition_by))
+        }
+        // TODO: handle years
+        _ => return Err(RetentionPeriodError::UnsupportedPartitionBy(p.partition_by)),
+    };
+    cutoff.ok_or(RetentionPeriodError::InvalidAmount(
+        p.amount,
+        p.partition_by,
+    ))
+}
+
+// Start of the month `months` calendar months before the one `ts` falls in.
+// Months differ in length, so the cutoff always sits on a MONTH partition
+// boundary rather than on a day that may not exist, e.g. 31 Nov.
+fn months_before(ts: DateTime<Utc>, months: i64) -> Option<DateTime<Utc>> {
+    let months = Months::new(u32::try_from(months).ok()?);
+    align_to_partition(ts, &PartitionBy::Month).checked_sub_months(months)
+}
+
+// QuestDB timestamps have microsecond precision, both patterns describe the same layout
+const QUESTDB_TIMESTAMP_PATTERN: &str = "yyyy-MM-ddTHH:mm:ss.SSSUUU";
+const CHRONO_TIMESTAMP_PATTERN: &str = "%Y-%m-%dT%H:%M:%S%.6f";
+
+// Pair of patterns used to pass cutoffs to to_timestamp(), overridable from the config
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize, JsonSchema)]
+struct TimestampPattern {
+    questdb: String,
+    chrono: String,
+}
+
+impl Default for TimestampPattern {
+    fn default() -> Self {
+        TimestampPattern {
+            questdb: QUESTDB_TIMESTAMP_PATTERN.to_string(),
+            chrono: CHRONO_TIMESTAMP_PATTERN.to_string(),
+        }
+    }
+}
+
+// How cutoffs are written into statements
+#[derive(Debug, Clone, Copy, Default, PartialEq, Serialize, Deserialize, JsonSchema)]
+#[serde(rename_all = "snake_case")]
+enum TimestampStyle {
+    // '2023-01-01T00:00:00.000000Z', compared natively, no pattern to get wrong
+    #[default]
+    Literal,
+    // to_timestamp('...', 'pattern'), for servers that do not accept the literal
+    ToTimestamp,
+}
+
+// Set once at startup, after the config has been validated
+static TIMESTAMP_PATTERN: OnceLock<TimestampPattern> = OnceLock::new();
+static TIMESTAMP_STYLE: OnceLock<TimestampStyle> = OnceLock::new();
+
+// Renders `ts` the way QuestDB would read it back with `pattern`, for the tokens we emit
+fn render_questdb_pattern(pattern: &str, ts: &DateTime<Utc>) -> Result<String> {
+    const TOKENS: &[(&str, &str)] = &[
+        ("yyyy", "%Y"),
+        ("MM", "%m"),
+        ("dd", "%d"),
+        ("HH", "%H"),
+        ("mm", "%M"),
+        ("ss", "%S"),
+        ("SSS", "%3f"),
+        ("UUU", "UUU"),
+    ];
+    let micros = format!("{:03}", ts.timestamp_subsec_micros() % 1000);
+    let mut out = String::new();
+    let mut rest = pattern;
+    'outer: while let Some(c) = rest.chars().next() {
+        for (token, strftime) in TOKENS {
+            if let Some(tail) = rest.strip_prefix(token) {
+                if *token == "UUU" {
+                    out.push_str(&micros);
+                } else {
+                    out.push_str(&ts.format(strftime).to_string());
+                }
+                rest = tail;
+                continue 'outer;
+            }
+        }
+        if c.is_ascii_alphabetic() && c != 'T' {
+            bail!("unsupported token '{}' in QuestDB pattern '{}'", c, pattern);
+        }
+        out.push(c);
+        rest = &rest[c.len_utf8()..];
+    }
+    Ok(out)
+}
+
+impl TimestampPattern {
+    // Both patterns must produce the same text for a reference timestamp, which
+    // catches swapped fields and lost precision before any SQL is sent
+    fn validate(&self) -> Result<()> {
+        if StrftimeItems::new(&self.chrono).any(|i| i == Item::Error) {
+            bail!("invalid strftime pattern '{}'", self.chrono);
+        }
+        let reference = Utc.with_ymd_and_hms(2023, 11, 22, 13, 44, 55).unwrap()
+            + Duration::microseconds(123_456);
+        let questdb = render_questdb_pattern(&self.questdb, &reference)?;
+        let chrono = reference.format(&self.chrono).to_string();
+        if questdb != chrono {
+            bail!(
+                "timestamp patterns '{}' and '{}' are inconsistent: '{}' != '{}'",
+                self.questdb,
+                self.chrono,
+                questdb,
+                chrono
+            );
+        }
+        Ok(())
+    }
+}
+
+fn to_timestamp_sql(ts: &DateTime<Utc>) -> String {
+    let style = TIMESTAMP_STYLE.get_or_init(TimestampStyle::default);
+    let pattern = TIMESTAMP_PATTERN.get_or_init(TimestampPattern::default);
+    format_timestamp_sql(ts, *style, pattern)
+}
+
+fn format_timestamp_sql(
+    ts: &DateTime<Utc>,
+    style: TimestampStyle,
+    pattern: &TimestampPattern,
+) -> String {
+    match style {
+        TimestampStyle::Literal => format!("'{}Z'", ts.format(CHRONO_TIMESTAMP_PATTERN)),
+        TimestampStyle::ToTimestamp => format!(
+            "to_timestamp('{}', '{}')",
+            ts.format(&pattern.chrono),
+            pattern.questdb
+        ),
+    }
+}
+
+// Keywords that have no business in a DROP PARTITION predicate
+const FORBIDDEN_WHERE_KEYWORDS: &[&str] = &[
+    "alter", "create", "delete", "drop", "insert", "rename", "truncate", "update", "select",
+];
+
+// DROP PARTITION WHERE is evaluated against partition timestamps, so extra
+// predicates must be read-only and reference the designated timestamp.
+fn predicate_words(clause: &str) -> Vec<String> {
+    clause
+        .split(|c: char| !c.is_alphanumeric() && c != '_')
+        .map(|w| w.to_lowercase())
+        .collect()
+}
+
+// Why a user-supplied predicate cannot be spliced into a statement, if it cannot
+fn unsafe_fragment_reason(clause: &str) -> Option<String> {
+    if clause.contains(';') || clause.contains("--") || clause.contains("/*") {
+        return Some(String::from(
+            "statement separators and comments are not allowed",
+        ));
+    }
+    predicate_words(clause)
+        .iter()
+        .find(|w| FORBIDDEN_WHERE_KEYWORDS.contains(&w.as_str()))
+        .map(|w| format!("keyword '{}' is not allowed", w))
+}
+
+fn validate_where_clause(
+    clause: &str,
+    timestamp_col: &str,
+    mode: RetentionMode,
+) -> Result<(), RetentionPeriodError> {
+    let invalid = |reason: &str| {
+        Err(RetentionPeriodError::InvalidWhereClause(
+            clause.to_string(),
+            reason.to_string(),
+        ))
+    };
+
+    if let Some(reason) = unsafe_fragment_reason(clause) {
+        return invalid(&reason);
+    }
+    // DELETE filters on any column, only partition statements need the timestamp
+    let words = predicate_words(clause);
+    if mode != RetentionMode::Rows && !words.contains(&timestamp_col.to_lowercase()) {
+        return invalid(&format!(
+            "DROP PARTITION only filters on partition timestamps, the predicate must reference '{}'",
+            timestamp_col
+        ));
+    }
+    Ok(())
+}
+
+// Parses durations like "90s", "30m", "12h", "7d" or "2w"
+fn parse_unit(input: &str) -> Result<PartitionBy, RetentionPeriodError> {
+    PartitionBy::from_str(&input.to_uppercase())
+}
+
+fn parse_duration(input: &str) -> Result<Duration, String> {
+    let invalid = || {
+        format!(
+            "invalid duration '{}', expected e.g. 30m, 12h, 7d or 2w",
+            input
+        )
+    };
+    let input = input.trim();
+    let split = input
+        .find(|c: char| !c.is_ascii_digit())
+        .ok_or_else(invalid)?;
+    let amount = input[..split].parse::<i64>().map_err(|_| invalid())?;
+    let duration = match &input[split..] {
+        "s" => Duration::try_seconds(amount),
+        "m" => Duration::try_minutes(amount),
+        "h" => Duration::try_hours(amount),
+        "d" => Duration::try_days(amount),
+        "w" => Duration::try_weeks(amount),
+        _ => None,
+    };
+    duration.ok_or_else(invalid)
+}
+
+// Truncates `ts` down to the start of the partition it falls into. Weeks
+// start on Monday, as they do for QuestDB's WEEK partitions.
+fn align_to_partition(ts: DateTime<Utc>, partition_by: &PartitionBy) -> DateTime<Utc> {
+    let date = ts.naive_utc().date();
+    let start = match partition_by {
+        PartitionBy::None => return ts,
+        PartitionBy::Hour => date.and_hms_opt(ts.hour(), 0, 0),
+        PartitionBy::Day => date.and_hms_opt(0, 0, 0),
+        PartitionBy::Week => (date - Duration::days(date.weekday().num_days_from_monday() as i64))
+            .and_hms_opt(0, 0, 0),
+        PartitionBy::Month => date.with_day(1).and_then(|d| d.and_hms_opt(0, 0, 0)),
+        PartitionBy::Year => date.with_ordinal(1).and_then(|d| d.and_hms_opt(0, 0, 0)),
+    };
+    Utc.from_utc_datetime(&start.expect("start of a partition is a valid time"))
+}
+
+// The time a table's retention is counted back from: now or its newest row, see Anchor
+fn cutoff_anchor(
+    client: &mut Client,
+    table: &str,
+    table_config: &TableConfig,
+) -> Result<DateTime<Utc>> {
+    let now = reference_now();
+    if table_config.anchor == Anchor::Now {
+        return Ok(now);
+    }
+    let newest = match &table_config.timestamp_column {
+        Some(col) => get_newest_value(client, table, col).map_err(|e| anyhow!(e)),
+        None => get_newest_timestamp(client, table),
+    }
+    .context("failed to query the newest timestamp")?;
+    // an empty table has nothing to anchor to, and future rows must not push the cutoff forward
+    Ok(newest.map_or(now, |n| n.min(now)))
+}
+
+// Computes the oldest timestamp to keep, clamped so it is never more recent than `floor` ago
+fn compute_cutoff(
+    table: &str,
+    anchor: DateTime<Utc>,
+    p: RetentionPeriod,
+    floor: Option<Duration>,
+    align_to: Option<&PartitionBy>,
+) -> Result<DateTime<Utc>, RetentionPeriodError> {
+    let mut cutoff = get_oldest_timestamp(anchor, p)?;
+    if let Some(partition_by) = align_to {
+        cutoff = align_to_partition(cutoff, partition_by);
+    }
+    clamp_to_floor(table, cutoff, floor)
+}
+
+fn clamp_to_floor(
+    table: &str,
+    cutoff: DateTime<Utc>,
+    floor: Option<Duration>,
+) -> Result<DateTime<Utc>, RetentionPeriodError> {
+    // the floor is always relative to the wall clock
+    if let Some(floor) = floor {
+        let latest = reference_now()
+            .checked_sub_signed(floor)
+            .ok_or(RetentionPeriodError::InvalidFloor(floor))?;
+        if cutoff > latest {
+            warn!(
+                "cutoff {} for {} is within the floor, clamping to {}",
+                display_ts(&cutoff),
+                table,
+                display_ts(&latest)
+            );
+            return Ok(latest);
+        }
+    }
+    Ok(cutoff)
+}
+
+// Start of the first partition kept when only the newest `keep_percent` of a
+// table's partitions survive. The newest partition is always kept.
+fn percent_cutoff(client: &mut Client, table: &str, keep_percent: f64) -> Result<DateTime<Utc>> {
+    let starts = list_partition_starts(client, table).context("failed to list the partitions")?;
+    let drop = ((starts.len() as f64) * (100.0 - keep_percent) / 100.0).floor() as usize;
+    match starts.get(drop.min(starts.len().saturating_sub(1))) {
+        Some(start) => Ok(*start),
+        // no partitions, nothing is older than now
+        None => Ok(reference_now()),
+    }
+}
+
+// The cutoff for a configured table: its retention (or keep_percent) counted back
+// from its anchor, then clamped by the floor and the keep-newest policy
+fn table_cutoff(
+    client: &mut Client,
+    table: &Table,
+    table_config: &TableConfig,
+    config: &Config,
+) -> Result<DateTime<Utc>> {
+    if !table_config.has_fixed_amount() && table.partition_by == PartitionBy::None {
+        bail!(RetentionPeriodError::InvalidPartitionBy(PartitionBy::None));
+    }
+    let cutoff = match (&table_config.cutoff_query, table_config.keep_percent) {
+        (Some(q), _) => {
+            let cutoff = query_cutoff(client, q)?;
+            clamp_to_floor(&table.name, cutoff, config.floor()?)?
+        }
+        (None, Some(pct)) => {
+            let cutoff = percent_cutoff(client, &table.name, pct)?;
+            clamp_to_floor(&table.name, cutoff, config.floor()?)?
+        }
+        (None, None) => {
+            let p = table_config.retention_period(&table.partition_by)?;
+            let anchor = cutoff_anchor(client, &table.name, table_config)?;
+            compute_cutoff(
+                &table.name,
+                anchor,
+                p,
+                config.floor()?,
+                // the table's own partitions, which may be finer than `unit`
+                Some(&table.partition_by).filter(|_| table_config.align_to_partition),
+            )?
+        }
+    };
+    config.policy.keep_newest(client, &table.name, cutoff)
+}
+
+// Runs a user's cutoff_query, which must return exactly one non-null timestamp
+fn query_cutoff(client: &mut Client, query: &str) -> Result<DateTime<Utc>> {
+    let rows = client
+        .query(query, &[])
+        .with_context(|| format!("cutoff_query failed: '{}'", query))?;
+    let row = match rows.as_slice() {
+        [row] if row.len() == 1 => row,
+        [row] => bail!(
+            "cutoff_query must return a single column, got {}",
+            row.len()
+        ),
+        _ => bail!("cutoff_query must return a single row, got {}", rows.len()),
+    };
+    let ts: Option<NaiveDateTime> = row
+        .try_get(0)
+        .with_context(|| format!("cutoff_query must return a timestamp: '{}'", query))?;
+    let ts = ts.with_context(|| format!("cutoff_query returned null: '{}'", query))?;
+    Ok(Utc.from_utc_datetime(&ts))
+}
+
+// Time between checks while waiting for dropped partitions to disappear
+const VERIFY_POLL_INTERVAL: std::time::Duration = std::time::Duration::from_millis(500);
+
+// Polls table_partitions() until none of `targets` is listed; WAL tables apply the
+// DROP after it returns. Errors when some are still there after `timeout_secs`.
+fn verify_partitions_gone(
+    client: &mut Client,
+    table: &str,
+    targets: &[String],
+    timeout_secs: u64,
+) -> Result<()> {
+    let deadline = Instant::now() + std::time::Duration::from_secs(timeout_secs);
+    loop {
+        let present: Vec<String> = list_partition_ranges(client, table)
+            .context("failed to verify the dropped partitions")?
+            .into_iter()
+            .map(|r| r.name)
+            .filter(|n| targets.contains(n))
+            .collect();
+        if present.is_empty() {
+            return Ok(());
+        }
+        let now = Instant::now();
+        if now >= deadline {
+            bail!(
+                "{} of {} targeted partitions of {} are still present {}s after the drop: {}",
+                present.len(),
+                targets.len(),
+                table,
+                timeout_secs,
+                present.join(", ")
+            );
+        }
+        std::thread::sleep(VERIFY_POLL_INTERVAL.min(deadline - now));
+    }
+}
+
+// What a run did to a table, for reporting
+#[derive(Debug, Clone)]
+struct RunOutcome {
+    cutoff: DateTime<Utc>,
+    rows_deleted: u64,
+    partitions_dropped: u64,
+    sql: String,
+    timestamp_col: String,
+}
+
+fn run(
+    client: &mut Client,
+    table: &str,
+    timestamp_col: &str,
+    timestamp: DateTime<Utc>,
+    table_config: &TableConfig,
+) -> Result<Option<RunOutcome>> {
+    let statements = retention_statements(client, table, timestamp_col, timestamp, table_config)?;
+    if statements.is_empty() {
+        return Ok(None);
+    }
+    // DROP PARTITION reports rows unreliably, the partition count is what changed
+    let before = count_partitions(client, table).context("failed to count partitions")?;
+    let mut deleted = 0;
+    for query in &statements {
+        deleted += execute_statement(client, table, query)?;
+    }
+    let after = count_partitions(client, table).context("failed to count partitions")?;
+    Ok(Some(RunOutcome {
+        cutoff: timestamp,
+        rows_deleted: deleted,
+        partitions_dropped: before.saturating_sub(after).max(0) as u64,
+        sql: statements.join(";\n"),
+        timestamp_col: timestamp_col.to_string(),
+    }))
+}
+
+// The only place a statement that changes data is sent, so the read-only gate
+// covers every mode
+fn execute_statement(client: &mut Client, table: &str, query: &str) -> Result<u64> {
+    check_writable(&format!("run '{}'", query))?;
+    client.execute(query, &[]).map_err(|e| {
+        if is_partition_in_use(&e) {
+            anyhow!(e).context(RetentionPeriodError::PartitionInUse(table.to_string()))
+        } else {
+            anyhow!(e).context(format!("failed to execute '{}'", query))
+        }
+    })
+}
+
+// Fragments of the errors QuestDB returns when a partition is held by a writer
+const PARTITION_IN_USE_MESSAGES: &[&str] = &[
+    "table busy",
+    "partition is in use",
+    "active partition",
+    "locked",
+];
+
+fn is_partition_in_use(e: &postgres::Error) -> bool {
+    e.as_db_error().is_some_and(|db| {
+        let message = db.message().to_lowercase();
+        PARTITION_IN_USE_MESSAGES
+            .iter()
+            .any(|m| message.contains(m))
+    })
+}
+
+// The statements to run for a table in order, empty if nothing predates the cutoff
+fn retention_statements(
+    client: &mut Client,
+    table: &str,
+    timestamp_col: &str,
+    timestamp: DateTime<Utc>,
+    table_config: &TableConfig,
+) -> Result<Vec<String>> {
+    let where_clause = table_config.where_clause.as_deref();
+    if let Some(clause) = where_clause {
+        validate_where_clause(clause, timestamp_col, table_config.mode)?;
+    }
+
+    // Nothing to do if no partition (or, for a non-designated column, no row) predates the cutoff
+    let oldest = match table_config.timestamp_column {
+        Some(..) => get_oldest_value(client, table, timestamp_col).map_err(|e| anyhow!(e)),
+        None => get_oldest_partition(client, table),
+    };
+    match oldest.context("failed to query the oldest timestamp")? {
+        Some(oldest) if oldest < timestamp => {}
+        _ => return Ok(Vec::new()),
+    }
+
+    if let Some(preserve) = &table_config.preserve {
+        return preserving_statements(client, table, timestamp_col, &timestamp, preserve);
+    }
+
+    match table_config.max_drops_per_run {
+        Some(max) => {
+            let mut names = list_droppable_partitions(client, table, &timestamp)
+                .context("failed to list the droppable partitions")?;
+            if names.is_empty() {
+                return Ok(Vec::new());
+            }
+            names.truncate(max);
+            Ok(vec![partition_list_query(table, &names, table_config.mode)])
+        }
+        None => Ok(vec![retention_query(
+            table,
+            timestamp_col,
+            timestamp,
+            where_clause,
+            table_config.mode,
+        )]),
+    }
+}
+
+// DROP PARTITION is all or nothing: eligible partitions without preserved rows
+// are dropped, the others only lose their unpreserved rows through a DELETE
+fn preserving_statements(
+    client: &mut Client,
+    table: &str,
+    timestamp_col: &str,
+    cutoff: &DateTime<Utc>,
+    preserve: &str,
+) -> Result<Vec<String>> {
+    let mut drop = Vec::new();
+    let mut delete_until = None;
+    for p in list_droppable_ranges(client, table, cutoff)
+        .context("failed to list the droppable partitions")?
+    {
+        let preserved =
+            count_preserved_rows(client, table, timestamp_col, (&p.min, &p.max), preserve)
+                .with_context(|| {
+                    format!("failed to count preserved rows in partition {}", p.name)
+                })?;
+        if preserved == 0 {
+            drop.push(p.name);
+        } else if preserved < p.rows {
+            delete_until = Some(p.max);
+        }
+    }
+
+    let mut statements = Vec::new();
+    if !drop.is_empty() {
+        statements.push(partition_list_query(
+            table,
+            &drop,
+            RetentionMode::Partitions,
+        ));
+    }
+    if let Some(until) = delete_until {
+        statements.push(format!(
+            "DELETE FROM {} WHERE {} <= {} AND NOT ({})",
+            table,
+            quote_ident(timestamp_col),
+            to_timestamp_sql(&until),
+            preserve
+        ));
+    }
+    Ok(statements)
+}
+
+// Drops (or detaches) exactly the named partitions, used when a run is capped
+fn partition_list_query(table: &str, names: &[String], mode: RetentionMode) -> String {
+    let action = match mode {
+        RetentionMode::Detach => "DETACH",
+        _ => "DROP",
+    };
+    let list: Vec<String> = names.iter().map(|n| format!("'{}'", n)).collect();
+    format!(
+        "ALTER TABLE {} {} PARTITION LIST {}",
+        table,
+        action,
+        list.join(", ")
+    )
+}
+
+// How identifiers are quoted in generated statements
+#[derive(Debug, Clone, Copy, Default, PartialEq, Serialize, Deserialize, JsonSchema)]
+#[serde(rename_all = "snake_case")]
+enum IdentifierQuoting {
+    // "name", QuestDB's own style
+    #[default]
+    Double,
+    // `name`, for query routers that expect MySQL-style quoting
+    Backtick,
+    // name as is, reserved words and special characters are left to the server
+    None,
+}
+
+// Set once at startup, like TIMESTAMP_STYLE
+static IDENTIFIER_QUOTING: OnceLock<IdentifierQuoting> = OnceLock::new();
+
+// Quotes an identifier so reserved words and special characters survive
+fn quote_ident(name: &str) -> String {
+    let quoting = IDENTIFIER_QUOTING.get_or_init(IdentifierQuoting::default);
+    quote_ident_with(name, *quoting)
+}
+
+fn quote_ident_with(name: &str, quoting: IdentifierQuoting) -> String {
+    match quoting {
+        IdentifierQuoting::Double => format!("\"{}\"", name.replace('"', "\"\"")),
+        IdentifierQuoting::Backtick => format!("`{}`", name.replace('`', "``")),
+        IdentifierQuoting::None => name.to_string(),
+    }
+}
+
+// Builds the statement that drops all partitions (or rows) earlier than `timestamp`
+fn retention_query(
+    table: &str,
+    timestamp_col: &str,
+    timestamp: DateTime<Utc>,
+    where_clause: Option<&str>,
+    mode: RetentionMode,
+) -> String {
+    let timestamp_col = quote_ident(timestamp_col);
+    let mut query = match mode {
+        RetentionMode::Partitions => format!(
+            "ALTER TABLE {} DROP PARTITION WHERE {} < {}",
+            table,
+            timestamp_col,
+            to_timestamp_sql(&timestamp)
+        ),
+        RetentionMode::Rows => format!(
+            "DELETE FROM {} WHERE {} < {}",
+            table,
+            timestamp_col,
+            to_timestamp_sql(&timestamp)
+        ),
+        RetentionMode::Detach => format!(
+            "ALTER TABLE {} DETACH PARTITION WHERE {} < {}",
+            table,
+            timestamp_col,
+            to_timestamp_sql(&timestamp)
+        ),
+    };
+    if let Some(clause) = where_clause {
+        query = format!("{} AND ({})", query, clause);
+    }
+    query
+}
+
+// Placeholder designated timestamp for offline linting, see validate_sql_offline
+const OFFLINE_TIMESTAMP_COL: &str = "timestamp";
+
+// Balanced quotes and parentheses and a single statement; no real parser, but
+// enough to catch a broken where_clause or pattern before it reaches a server
+fn sql_syntax_error(sql: &str) -> Option<String> {
+    if !sql.starts_with("ALTER TABLE ") && !sql.starts_with("DELETE FROM ") {
+        return Some(String::from("expected ALTER TABLE or DELETE FROM"));
+    }
+    let mut quote: Option<char> = None;
+    let mut depth = 0;
+    for c in sql.chars() {
+        match (quote, c) {
+            // a doubled quote closes and reopens, which is how quotes are escaped
+            (Some(q), c) if c == q => quote = None,
+            (Some(..), _) => {}
+            (None, '\'' | '"' | '`') => quote = Some(c),
+            (None, '(') => depth += 1,
+            (None, ')') if depth == 0 => return Some(String::from("unbalanced ')'")),
+            (None, ')') => depth -= 1,
+            (None, ';') => return Some(String::from("more than one statement")),
+            _ => {}
+        }
+    }
+    match (quote, depth) {
+        (Some(q), _) => Some(format!("unterminated {} quote", q)),
+        (None, 0) => None,
+        (None, _) => Some(String::from("unclosed '('")),
+    }
+}
+
+// --validate-sql-only: builds each configured table's statement from the config
+// alone. The cutoff is now and the column is timestamp_column or a placeholder,
+// so only the shape of the SQL is checked, not what it would drop.
+fn validate_sql_offline(config: &Config) -> Result<()> {
+    let mut names: Vec<&String> = config.tables.keys().collect();
+    names.sort();
+    let mut invalid = 0;
+    for name in names {
+        let table_config = &config.tables[name];
+        let col = table_config
+            .timestamp_column
+            .as_deref()
+            .unwrap_or(OFFLINE_TIMESTAMP_COL);
+        // whether a where_clause references the designated timestamp needs metadata
+        let checked = match &table_config.where_clause {
+            Some(clause) => unsafe_fragment_reason(clause).map_or(Ok(()), Err),
+            None => Ok(()),
+        };
+        let sql = retention_query(
+            name,
+            col,
+            reference_now(),
+            table_config.where_clause.as_deref(),
+            table_config.mode,
+        );
+        match checked.and_then(|_| sql_syntax_error(&sql).map_or(Ok(()), Err)) {
+            Ok(()) => println!("ok   {}: {}", name, sql),
+            Err(reason) => {
+                invalid += 1;
+                println!("FAIL {}: {}\n  {}", name, reason, sql);
+            }
+        }
+    }
+    if !config.defaults.is_empty() || !config.prefixes.is_empty() || !config.by_partition.is_empty()
+    {
+        eprintln!(
+            "defaults, prefixes and by_partition entries need the table list and were not checked"
+        );
+    }
+    if invalid > 0 {
+        bail!(
+            "{} of {} statements are invalid",
+            invalid,
+            config.tables.len()
+        );
+    }
+    Ok(())
+}
+
+// How cutoff timestamps are printed
+#[derive(Debug, Clone)]
+enum TimestampFormat {
+    Default,
+    Rfc3339,
+    EpochMillis,
+    Custom(String),
+}
+
+// --timestamp-format, for warnings printed where no format is passed down
+static DISPLAY_FORMAT: OnceLock<TimestampFormat> = OnceLock::new();
+
+fn display_ts(ts: &DateTime<Utc>) -> String {
+    DISPLAY_FORMAT
+        .get_or_init(|| TimestampFormat::Default)
+        .format(ts)
+}
+
+impl TimestampFormat {
+    fn format(&self, ts: &DateTime<Utc>) -> String {
+        match self {
+            TimestampFormat::Default => ts.to_string(),
+            TimestampFormat::Rfc3339 => ts.to_rfc3339(),
+            TimestampFormat::EpochMillis => ts.timestamp_millis().to_string(),
+            TimestampFormat::Custom(pattern) => ts.format(pattern).to_string(),
+        }
+    }
+}
+
+impl FromStr for TimestampFormat {
+    type Err = String;
+
+    fn from_str(input: &str) -> Result<TimestampFormat, Self::Err> {
+        match input {
+            "default" => Ok(TimestampFormat::Default),
+            "rfc3339" => Ok(TimestampFormat::Rfc3339),
+            "epoch-millis" => Ok(TimestampFormat::EpochMillis),
+            _ => {
+                if StrftimeItems::new(input).any(|i| i == Item::Error) {
+                    Err(format!("invalid strftime pattern: '{}'", input))
+                } else {
+                    Ok(TimestampFormat::Custom(input.to_string()))
+                }
+            }
+        }
+    }
+}
+
+#[derive(Parser, Debug)]
+#[command(author, version, about, long_about = None)]
+struct Args {
+    /// Config file, may be repeated to layer overrides on top of a base config
+    #[arg(short, long)]
+    config_path: Vec<String>,
+
+    #[arg(short, long)]
+    interactive: bool,
+
+    /// Answer yes to all confirmation prompts
+    #[arg(short = 'y', long)]
+    assume_yes: bool,
+
+    /// Format of printed timestamps: default, rfc3339, epoch-millis or a strftime pattern
+    #[arg(long, default_value = "default")]
+    timestamp_format: TimestampFormat,
+
+    /// Print the reasoning behind each table's action before running
+    #[arg(long)]
+    explain: bool,
+
+    /// Output format for config runs
+    #[arg(long, value_enum, default_value = "text")]
+    output: OutputFormat,
+
+    /// When to color text output
+    #[arg(long, value_enum, default_value = "auto")]
+    color: ColorChoice,
+
+    /// Seconds to wait for the connection, overriding connect_timeout_secs from the config
+    #[arg(long)]
+    connect_timeout_secs: Option<u64>,
+
+    /// Database to connect to, overriding the one in the config
+    #[arg(long)]
+    db: Option<String>,
+
+    /// Connect using the named entry of `profiles` in the config
+    #[arg(long)]
+    profile: Option<String>,
+
+    /// Retention for a table as TABLE=DURATION, e.g. trades=30d, may be repeated
+    #[arg(long = "policy", value_name = "TABLE=DURATION")]
+    policies: Vec<InlinePolicy>,
+
+    /// Retention per table from a CSV file with table,amount,unit columns, e.g. trades,30,d
+    #[arg(long, value_name = "PATH")]
+    policy_csv: Option<String>,
+
+    /// Stop before the next table once the run has taken longer than this, e.g. 30m.
+    /// A statement still running 5 minutes past it aborts the process
+    #[arg(long, value_parser = parse_duration)]
+    max_runtime: Option<Duration>,
+
+    /// Report the current table and elapsed time on stderr this often, e.g. 30s
+    #[arg(long, value_parser = parse_duration)]
+    heartbeat: Option<Duration>,
+
+    /// Run the config up to this many times, stopping once a pass removes nothing
+    #[arg(long, default_value_t = 1, value_parser = clap::value_parser!(u32).range(1..))]
+    repeat: u32,
+
+    /// Time to wait between --repeat passes, e.g. 1m
+    #[arg(long, value_parser = parse_duration)]
+    delay: Option<Duration>,
+
+    /// Print every table with its partitioning, then exit
+    #[arg(long)]
+    list: bool,
+
+    /// With --list, also show each table's oldest and newest data (one query per table)
+    #[arg(long, requires = "list")]
+    with_range: bool,
+
+    /// Leave tables that are already up to date out of reports, plans and estimates
+    #[arg(long)]
+    only_actionable: bool,
+
+    /// Exit non-zero if anything warned, or a table was skipped or failed
+    #[arg(long)]
+    strict: bool,
+
+    /// Use each table's own TTL from QuestDB metadata over the configured amount
+    #[arg(long)]
+    use_table_metadata: bool,
+
+    /// Apply retention to this one table without a config, with --amount and --unit
+    #[arg(long, requires_all = ["amount", "unit"], conflicts_with_all = ["config_path", "interactive", "policies", "policy_csv"])]
+    table: Option<String>,
+
+    /// Number of --unit periods of --table to keep
+    #[arg(long, requires = "table")]
+    amount: Option<i64>,
+
+    /// Unit of --amount: HOUR, DAY, WEEK or MONTH, in any case
+    #[arg(long, requires = "table", value_parser = parse_unit)]
+    unit: Option<PartitionBy>,
+
+    /// Write a JSON report of every table and its computed cutoff to this file, then exit; gzipped when it ends in .gz
+    #[arg(long, value_name = "PATH")]
+    export_plan: Option<String>,
+
+    /// Write the generated statements to this file for manual review instead of running them; gzipped when it ends in .gz
+    #[arg(long, value_name = "FILE", conflicts_with_all = ["interactive", "table"])]
+    emit_sql: Option<String>,
+
+    /// Remove detached partition directories older than the cutoff, instead of only listing them
+    #[arg(long)]
+    purge_detached: bool,
+
+    /// Skip the tables an interrupted run already completed, as recorded in resume_file
+    #[arg(long)]
+    resume: bool,
+
+    /// Process up to this many tables at once, each on its own connection; tables sharing a group run one at a time
+    #[arg(long, value_name = "N", default_value_t = 1, value_parser = clap::value_parser!(u16).range(1..))]
+    concurrency: u16,
+
+    /// Have the server parse each generated statement without executing it
+    #[arg(long)]
+    check_sql: bool,
+
+    /// Lint the statements for the config's tables offline, without connecting to QuestDB
+    #[arg(long, requires = "config_path")]
+    validate_sql_only: bool,
+
+    /// Allow destructive runs against a config tagged `environment: production`
+    /// (also CONFIRM_PROD=1)
+    #[arg(long)]
+    confirm_production: bool,
+
+    #[command(subcommand)]
+    command: Option<Command>,
+}
+
+// A table's retention given on the command line instead of in a config
+#[derive(Debug, Clone)]
+struct InlinePolicy {
+    table: String,
+    retain: Duration,
+}
+
+impl FromStr for InlinePolicy {
+    type Err = String;
+
+    fn from_str(input: &str) -> Result<InlinePolicy, Self::Err> {
+        let (table, retain) = input
+            .split_once('=')
+            .filter(|(t, _)| !t.trim().is_empty())
+            .ok_or_else(|| {
+                format!(
+                    "invalid policy '{}', expected TABLE=DURATION, e.g. trades=30d",
+                    input
+                )
+            })?;
+        let retain = parse_duration(retain)?;
+        if retain <= Duration::zero() {
+            return Err(format!(
+                "invalid policy '{}', duration must be positive",
+                input
+            ));
+        }
+        Ok(InlinePolicy {
+            table: table.trim().to_string(),
+            retain,
+        })
+    }
+}
+
+#[derive(Debug, Deserialize)]
+struct PolicyRow {
+    table: String,
+    amount: i64,
+    unit: String,
+}
+
+// --policy-csv rows, each the same as a --policy; errors name the line
+fn read_policy_csv(path: &str) -> Result<Vec<InlinePolicy>> {
+    let mut reader = csv::ReaderBuilder::new()
+        .trim(csv::Trim::All)
+        .from_path(path)
+        .with_context(|| format!("failed to open {}", path))?;
+    let headers = reader
+        .headers()
+        .with_context(|| format!("failed to read the header of {}", path))?
+        .clone();
+    let mut policies = Vec::new();
+    for record in reader.records() {
+        let record = record.map_err(|e| {
+            let line = e.position().map_or(0, |p| p.line());
+            anyhow!("{}:{}: {}", path, line, e)
+        })?;
+        let line = record.position().map_or(0, |p| p.line());
+        let row: PolicyRow = record
+            .deserialize(Some(&headers))
+            .map_err(|e| anyhow!("{}:{}: {}", path, line, e))?;
+        let policy = format!("{}={}{}", row.table, row.amount, policy_unit(&row.unit));
+        let policy =
+            InlinePolicy::from_str(&policy).map_err(|e| anyhow!("{}:{}: {}", path, line, e))?;
+        policies.push(policy);
+    }
+    Ok(policies)
+}
+
+// Spreadsheet-friendly spellings of the --policy duration units
+fn policy_unit(unit: &str) -> &str {
+    match unit.to_lowercase().trim_end_matches('s') {
+        "minute" => "m",
+        "hour" => "h",
+        "day" => "d",
+        "week" => "w",
+        _ => unit,
+    }
+}
+
+// Converts a duration into a number of `partition_by` units to keep, rounding
+// up so that at least the requested span is retained
+fn duration_to_amount(table: &str, d: Duration, partition_by: &PartitionBy) -> Result<i64> {
+    let unit = match partition_by {
+        PartitionBy::Hour => Duration::hours(1),
+        PartitionBy::Day => Duration::days(1),
+        PartitionBy::Week => Duration::weeks(1),
+        _ => bail!(RetentionPeriodError::UnsupportedPartitionBy(
+            partition_by.clone()
+        )),
+    };
+    let (secs, unit_secs) = (d.num_seconds(), unit.num_seconds());
+    let amount = (secs + unit_secs - 1) / unit_secs;
+    if secs % unit_secs != 0 {
+        warn!(
+            "{} counts retention in {}s, keeping {} {}s",
+            table, partition_by, amount, partition_by
+        );
+    }
+    Ok(amount)
+}
+
+// Per-table retention stored with the table itself. QuestDB has no table
+// comments, but 8.1+ reports each table's TTL in tables(); None on older servers.
+fn list_table_ttls(
+    client: &mut Client,
+    cols: &MetadataColumns,
+) -> Option<Vec<(String, i32, String)>> {
+    let query = format!(
+        "SELECT {}, ttlValue, ttlUnit FROM tables() WHERE ttlValue > 0",
+        cols.name
+    );
+    let rows = client.query(&query, &[]).ok()?;
+    rows.iter()
+        .map(|r| {
+            Some((
+                r.try_get(cols.name).ok()?,
+                r.try_get("ttlValue").ok()?,
+                r.try_get("ttlUnit").ok()?,
+            ))
+        })
+        .collect()
+}
+
+fn ttl_to_duration(value: i32, unit: &str) -> Option<Duration> {
+    let value = i64::from(value);
+    match unit.trim().to_uppercase().trim_end_matches('S') {
+        "HOUR" => Some(Duration::hours(value)),
+        "DAY" => Some(Duration::days(value)),
+        "WEEK" => Some(Duration::weeks(value)),
+        // months and years have no fixed length, like PARTITION BY MONTH/YEAR
+        _ => None,
+    }
+}
+
+// With --use-table-metadata, a table's own TTL replaces the configured amount,
+// other settings of a configured entry are kept
+fn apply_table_metadata(
+    client: &mut Client,
+    cols: &MetadataColumns,
+    config: &mut Config,
+) -> Result<()> {
+    let ttls = match list_table_ttls(client, cols) {
+        Some(ttls) => ttls,
+        None => {
+            warn!("this server does not report table TTLs (QuestDB 8.1+), using the config only");
+            return Ok(());
+        }
+    };
+    for (name, value, unit) in ttls {
+        let retain = match ttl_to_duration(value, &unit) {
+            Some(d) => d,
+            None => {
+                warn!(
+                    "ignoring the TTL of {}: {} {} is not supported",
+                    name, value, unit
+                );
+                continue;
+            }
+        };
+        let table = get_table(client, cols, &name)?;
+        let entry = config.tables.entry(name.clone()).or_default();
+        // in the entry's unit, so a configured `unit` keeps meaning the same thing
+        let amount = duration_to_amount(&name, retain, &entry.retention_unit(&table.partition_by))
+            .with_context(|| format!("invalid TTL for {}", name))?;
+        entry.amount = amount;
+        entry.keep_percent = None;
+        entry.cutoff_query = None;
+    }
+    Ok(())
+}
+
+// Applies the --policy and --policy-csv amounts, keeping the rest of a table's config
+fn apply_inline_policies(
+    client: &mut Client,
+    cols: &MetadataColumns,
+    config: &mut Config,
+    policies: &[InlinePolicy],
+) -> Result<()> {
+    for p in policies {
+        let table = get_table(client, cols, &p.table)?;
+        let entry = config.tables.entry(p.table.clone()).or_default();
+        let unit = entry.retention_unit(&table.partition_by);
+        let amount = duration_to_amount(&p.table, p.retain, &unit)
+            .with_context(|| format!("invalid policy for {}", p.table))?;
+        entry.amount = amount;
+        entry.keep_percent = None;
+        entry.cutoff_query = None;
+    }
+    Ok(())
+}
+
+#[derive(Subcommand, Debug)]
+enum Command {
+    /// Estimate the disk space freed by the configured retention, without dropping anything
+    Estimate,
+    /// Check cutoff and SQL generation against built-in scenarios, without a database
+    Selftest,
+    /// Connect, report the server version and table count, then exit
+    #[command(alias = "test-connection")]
+    Ping,
+    /// Show how retention changes between two config files, without a database
+    DiffConfig { old: String, new: String },
+    /// Print a JSON Schema of the config file, for editor completion and validation
+    Schema,
+    /// Show what DROP and DETACH would each do to the configured tables, without running either
+    CompareModes,
+}
+
+// How old data is removed from a table
+#[derive(Debug, Clone, Copy, Default, PartialEq, Serialize, Deserialize, JsonSchema)]
+#[serde(rename_all = "lowercase")]
+enum RetentionMode {
+    // DROP PARTITION, fast but only removes whole partitions
+    #[default]
+    Partitions,
+    // DELETE FROM, removes rows up to the exact cutoff but rewrites the
+    // partition the cutoff falls into, which is far slower on large tables
+    Rows,
+    // DETACH PARTITION, leaves the partition files on disk so they can be
+    // archived or reattached later
+    Detach,
+}
+
+// Per-table settings, written either as a bare amount or as a mapping
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize, JsonSchema)]
+struct TableConfig {
+    #[serde(default)]
+    amount: i64,
+    // what amount counts, HOUR, DAY, WEEK or MONTH, instead of the table's partitioning
+    #[serde(default)]
+    unit: Option<String>,
+    // keep the newest this many percent of partitions instead of a fixed amount
+    #[serde(default)]
+    keep_percent: Option<f64>,
+    // query returning the cutoff as a single timestamp, instead of amount or keep_percent
+    #[serde(default)]
+    cutoff_query: Option<String>,
+    #[serde(default)]
+    where_clause: Option<String>,
+    #[serde(default, alias = "strategy")]
+    mode: RetentionMode,
+    // oldest partitions dropped per run at most, so a first run on a long-lived
+    // table catches up gradually instead of removing years of data at once
+    #[serde(default)]
+    max_drops_per_run: Option<usize>,
+    // truncate the cutoff to the start of its partition, so whole partitions are kept
+    #[serde(default)]
+    align_to_partition: bool,
+    // retention key other than the designated timestamp, e.g. created_at
+    #[serde(default)]
+    timestamp_column: Option<String>,
+    #[serde(default)]
+    anchor: Anchor,
+    // rows matching this predicate survive retention, e.g. "flagged = true"
+    #[serde(default)]
+    preserve: Option<String>,
+    // fail the table if fewer rows than this remain once retention has run
+    #[serde(default)]
+    min_rows_after: Option<i64>,
+    // query whose first value must be truthy before anything is dropped, {cutoff}
+    // is replaced by the cutoff, e.g. to check a rollup watermark
+    #[serde(default)]
+    guard_query: Option<String>,
+    // tables in the same group never run at the same time under --concurrency,
+    // e.g. tables that share a volume
+    #[serde(default)]
+    group: Option<String>,
+}
+
+// What the retention window is counted back from
+#[derive(Debug, Clone, Copy, Default, PartialEq, Serialize, Deserialize, JsonSchema)]
+#[serde(rename_all = "lowercase")]
+enum Anchor {
+    #[default]
+    Now,
+    // the table's newest timestamp, so a table that stopped receiving data keeps its last window
+    Latest,
+}
+
+impl TableConfig {
+    // whether retention is a number of partitions rather than keep_percent or cutoff_query
+    fn has_fixed_amount(&self) -> bool {
+        self.keep_percent.is_none() && self.cutoff_query.is_none()
+    }
+
+    // The unit amount counts in, the table's partitioning unless `unit` is set
+    fn retention_unit(&self, partition_by: &PartitionBy) -> PartitionBy {
+        self.unit
+            .as_deref()
+            .and_then(|u| parse_unit(u).ok())
+            .unwrap_or_else(|| partition_by.clone())
+    }
+
+    fn retention_period(
+        &self,
+        partition_by: &PartitionBy,
+    ) -> Result<RetentionPeriod, RetentionPeriodError> {
+        // an unpartitioned table has nothing to drop, whatever the unit
+        if *partition_by == PartitionBy::None {
+            return Err(RetentionPeriodError::InvalidPartitionBy(PartitionBy::None));
+        }
+        new_retention_period(self.amount, self.retention_unit(partition_by))
+    }
+
+    // Why partitions before the cutoff go, for previews
+    fn drop_reason(&self, partition_by: &PartitionBy) -> String {
+        if self.cutoff_query.is_some() {
+            return String::from("older than the timestamp returned by cutoff_query");
+        }
+        if let Some(pct) = self.keep_percent {
+            return format!("outside the newest {}% of partitions", pct);
+        }
+        let from = match self.anchor {
+            Anchor::Now => "now",
+            Anchor::Latest => "the newest row",
+        };
+        format!(
+            "older than {} {}s counted back from {}",
+            self.amount,
+            self.retention_unit(partition_by),
+            from
+        )
+    }
+
+    fn retention_label(&self) -> String {
+        if let Some(q) = &self.cutoff_query {
+            return format!("cutoff_query={}", q);
+        }
+        match (self.keep_percent, &self.unit) {
+            (Some(pct), _) => format!("keep_percent={}", pct),
+            (None, Some(unit)) => format!("amount={} {}", self.amount, unit.to_uppercase()),
+            (None, None) => format!("amount={}", self.amount),
+        }
+    }
+
+    fn validate(&self) -> Result<()> {
+        if let Some(q) = &self.cutoff_query {
+            if q.trim().is_empty() {
+                bail!("cutoff_query must not be empty");
+            }
+            if self.amount != 0 || self.keep_percent.is_some() {
+                bail!("cutoff_query replaces amount and keep_percent, set only one of them");
+            }
+            if self.anchor != Anchor::Now || self.align_to_partition {
+                bail!("cutoff_query sets the cutoff itself, anchor and align_to_partition do not apply");
+            }
+        }
+        match self.keep_percent {
+            Some(pct) => {
+                if !(pct > 0.0 && pct <= 100.0) {
+                    bail!("keep_percent must be above 0 and at most 100, got {}", pct);
+                }
+                if self.amount != 0 {
+                    bail!("amount and keep_percent are mutually exclusive");
+                }
+                if self.anchor != Anchor::Now || self.align_to_partition {
+                    bail!("keep_percent already cuts on partition edges, anchor and align_to_partition do not apply");
+                }
+            }
+            None if self.amount <= 0 && self.cutoff_query.is_none() => {
+                bail!("amount must be at least 1, got {}", self.amount);
+            }
+            None => {}
+        }
+        if let Some(unit) = &self.unit {
+            if !matches!(
+                parse_unit(unit),
+                Ok(PartitionBy::Hour | PartitionBy::Day | PartitionBy::Week | PartitionBy::Month)
+            ) {
+                bail!("unit must be HOUR, DAY, WEEK or MONTH, got '{}'", unit);
+            }
+            if !self.has_fixed_amount() {
+                bail!("unit only applies to amount, not keep_percent or cutoff_query");
+            }
+        }
+        if self.group.as_deref().is_some_and(|g| g.trim().is_empty()) {
+            bail!("group must not be empty");
+        }
+        if let Some(max) = self.max_drops_per_run {
+            if max == 0 {
+                bail!("max_drops_per_run must be at least 1");
+            }
+            if self.mode == RetentionMode::Rows {
+                bail!("max_drops_per_run only applies to partition modes, not mode: rows");
+            }
+            if self.where_clause.is_some() {
+                bail!("max_drops_per_run cannot be combined with a where_clause");
+            }
+        }
+        if let Some(preserve) = &self.preserve {
+            if preserve.trim().is_empty() {
+                bail!("preserve must not be empty");
+            }
+            if let Some(reason) = unsafe_fragment_reason(preserve) {
+                bail!("invalid preserve '{}': {}", preserve, reason);
+            }
+            if self.mode != RetentionMode::Partitions {
+                bail!("preserve only applies to mode: partitions, use where_clause for mode: rows");
+            }
+            if self.where_clause.is_some() || self.max_drops_per_run.is_some() {
+                bail!("preserve cannot be combined with where_clause or max_drops_per_run");
+            }
+        }
+        if self.min_rows_after.is_some_and(|n| n < 0) {
+            bail!("min_rows_after must not be negative");
+        }
+        if let Some(q) = &self.guard_query {
+            if q.trim().is_empty() || q.contains(';') {
+                bail!("guard_query must be a single statement");
+            }
+        }
+        if let Some(col) = &self.timestamp_column {
+            if col.is_empty() || !col.chars().all(|c| c.is_alphanumeric() || c == '_') {
+                bail!("timestamp_column '{}' must be a plain column name", col);
+            }
+            if self.mode != RetentionMode::Rows {
+                bail!(
+                    "timestamp_column requires mode: rows, DROP PARTITION only filters on the designated timestamp"
+                );
+            }
+        }
+        Ok(())
+    }
+}
+
+// The two forms deserialize_tables accepts, described for the JSON schema; never built
+#[derive(JsonSchema)]
+#[serde(untagged)]
+#[allow(dead_code, clippy::large_enum_variant)]
+enum TableEntry {
+    Amount(i64),
+    Config(TableConfig),
+}
+
+// Accepts `table: 5` as well as the full mapping form
+fn deserialize_tables<'de, D>(d: D) -> Result<HashMap<String, TableConfig>, D::Error>
+where
+    D: serde::Deserializer<'de>,
+{
+    use serde::de::Error;
+
+    let entries: HashMap<String, serde_yaml::Value> = HashMap::deserialize(d)?;
+    entries
+        .into_iter()
+        .map(|(k, v)| {
+            let c = match v.as_i64() {
+                Some(amount) => TableConfig {
+                    amount,
+                    ..Default::default()
+                },
+                None => serde_yaml::from_value(v)
+                    .map_err(|e| D::Error::custom(format!("{}: {}", k, e)))?,
+            };
+            Ok((k, c))
+        })
+        .collect()
+}
+
+/// A retention config, as read from YAML or built with [`ConfigBuilder`]
+#[derive(Debug, Clone, Serialize, Deserialize, JsonSchema)]
+pub struct Config {
+    #[serde(default)]
+    version: Option<u32>,
+    #[serde(default, deserialize_with = "deserialize_tables")]
+    #[schemars(with = "HashMap<String, TableEntry>")]
+    tables: HashMap<String, TableConfig>,
+    #[serde(default)]
+    conn_str: Option<String>,
+    // alternative to conn_str, assembled into a connection string
+    #[serde(default)]
+    host: Option<String>,
+    #[serde(default)]
+    port: Option<u16>,
+    #[serde(default)]
+    user: Option<String>,
+    #[serde(default)]
+    password: Option<String>,
+    #[serde(default)]
+    password_file: Option<String>,
+    #[serde(default)]
+    database: Option<String>,
+    #[serde(default)]
+    min_table_size: Option<i64>,
+    #[serde(default)]
+    notify_url: Option<String>,
+    // glob pattern -> amount, applied to tables without an explicit entry
+    #[serde(default, deserialize_with = "deserialize_tables")]
+    #[schemars(with = "HashMap<String, TableEntry>")]
+    defaults: HashMap<String, TableConfig>,
+    // table name prefix -> amount, the longest matching prefix wins over defaults
+    #[serde(default, deserialize_with = "deserialize_tables")]
+    #[schemars(with = "HashMap<String, TableEntry>")]
+    prefixes: HashMap<String, TableConfig>,
+    // partition_by (HOUR, DAY...) -> amount, for tables matched by none of the above
+    #[serde(default, deserialize_with = "deserialize_tables")]
+    #[schemars(with = "HashMap<String, TableEntry>")]
+    by_partition: HashMap<String, TableConfig>,
+    // glob patterns of tables that are never processed
+    #[serde(default)]
+    exclude: Vec<String>,
+    // never drop data more recent than this, e.g. "7d"
+    #[serde(default)]
+    floor: Option<String>,
+    #[serde(default)]
+    policy: Policy,
+    // override for the to_timestamp() patterns, e.g. for a QuestDB release with different syntax
+    #[serde(default)]
+    timestamp_pattern: Option<TimestampPattern>,
+    // literal or to_timestamp, defaults to to_timestamp when timestamp_pattern is set
+    #[serde(default)]
+    timestamp_style: Option<TimestampStyle>,
+    // double (default), backtick or none, for proxies in front of QuestDB
+    #[serde(default)]
+    identifier_quoting: Option<IdentifierQuoting>,
+    // client (default) or server, the clock cutoffs are counted back from
+    #[serde(default)]
+    clock: Option<Clock>,
+    // enumerates the tables to consider instead of tables(), e.g. to scope to a prefix
+    #[serde(default)]
+    tables_query: Option<String>,
+    // error, skip or warn (default) when a configured table does not exist
+    #[serde(default)]
+    on_missing_table: Option<MissingTable>,
+    // give up on an unreachable host after this many seconds instead of hanging
+    #[serde(default)]
+    connect_timeout_secs: Option<u64>,
+    // QuestDB's db directory, to find partitions left behind by DETACH
+    #[serde(default)]
+    detached_root: Option<String>,
+    // match configured table names to existing tables ignoring case
+    #[serde(default)]
+    case_insensitive_tables: bool,
+    // columns tried in order when a table reports no designated timestamp
+    #[serde(default)]
+    timestamp_fallbacks: Vec<String>,
+    // named connections selected with --profile, replacing the top-level one
+    #[serde(default)]
+    profiles: HashMap<String, ConnectionProfile>,
+    // "production" requires --confirm-production before anything is dropped
+    #[serde(default)]
+    environment: Option<String>,
+    // same as --strict
+    #[serde(default)]
+    warnings_as_errors: bool,
+    // refuse every statement that changes data, as list, estimate and plan modes always do
+    #[serde(default)]
+    read_only: bool,
+    // while this file exists, runs exit without touching anything
+    #[serde(default)]
+    pause_file: Option<String>,
+    // tables completed so far are recorded here, for --resume after an interruption
+    #[serde(default)]
+    resume_file: Option<String>,
+    // after a drop, wait up to this many seconds for the partitions to be gone
+    #[serde(default)]
+    verify_drops_secs: Option<u64>,
+}
+
+// The connection settings of Config, as a named alternative
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize, JsonSchema)]
+struct ConnectionProfile {
+    #[serde(default)]
+    conn_str: Option<String>,
+    #[serde(default)]
+    host: Option<String>,
+    #[serde(default)]
+    port: Option<u16>,
+    #[serde(default)]
+    user: Option<String>,
+    #[serde(default)]
+    password: Option<String>,
+    #[serde(default)]
+    password_file: Option<String>,
+    #[serde(default)]
+    database: Option<String>,
+    // overrides the top-level environment when this profile is selected
+    #[serde(default)]
+    environment: Option<String>,
+}
+
+// Restrictions enforced at runtime, for configs handed to less-trusted operators.
+// Unset fields fall back to their defaults, so merged configs can tell them apart.
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize, JsonSchema)]
+struct Policy {
+    // whether a cutoff may drop every partition of a table, true by default
+    #[serde(default)]
+    allow_full_truncate: Option<bool>,
+    // how far an ad hoc amount may go below the configured one, e.g. "50%"
+    #[serde(default)]
+    max_retention_reduction: Option<String>,
+    // never let a cutoff reach into the newest (active) partition, true by default
+    #[serde(default)]
+    always_keep_newest: Option<bool>,
+}
+
+impl Policy {
+    fn allow_full_truncate(&self) -> bool {
+        self.allow_full_truncate.unwrap_or(true)
+    }
+
+    fn always_keep_newest(&self) -> bool {
+        self.always_keep_newest.unwrap_or(true)
+    }
+
+    // Field by field, so an overlay setting one field keeps the base's others
+    fn merge(self, overlay: Policy) -> Policy {
+        Policy {
+            allow_full_truncate: overlay.allow_full_truncate.or(self.allow_full_truncate),
+            max_retention_reduction: overlay
+                .max_retention_reduction
+                .or(self.max_retention_reduction),
+            always_keep_newest: overlay.always_keep_newest.or(self.always_keep_newest),
+        }
+    }
+
+    fn max_retention_reduction(&self) -> Result<Option<f64>> {
+        match &self.max_retention_reduction {
+            Some(r) => {
+                let pct = r
+                    .trim()
+                    .trim_end_matches('%')
+                    .parse::<f64>()
+                    .ok()
+                    .filter(|p| (0.0..=100.0).contains(p))
+                    .with_context(|| format!("invalid max_retention_reduction '{}'", r))?;
+                Ok(Some(pct / 100.0))
+            }
+            None => Ok(None),
+        }
+    }
+
+    // Moves the cutoff back to the start of the newest partition if it lies beyond it
+    fn keep_newest(
+        &self,
+        client: &mut Client,
+        table: &str,
+        cutoff: DateTime<Utc>,
+    ) -> Result<DateTime<Utc>> {
+        if !self.always_keep_newest() {
+            return Ok(cutoff);
+        }
+        match get_newest_partition_start(client, table)
+            .context("failed to query the newest partition")?
+        {
+            Some(start) if start < cutoff => {
+                warn!(
+                    "cutoff {} for {} reaches into the newest partition, clamping to {}",
+                    display_ts(&cutoff),
+                    table,
+                    display_ts(&start)
+                );
+                Ok(start)
+            }
+            _ => Ok(cutoff),
+        }
+    }
+
+    // `configured` is the amount from the config files, when the requested amount overrides it
+    fn check(
+        &self,
+        client: &mut Client,
+        table: &str,
+        cutoff: &DateTime<Utc>,
+        amount: i64,
+        configured: Option<i64>,
+    ) -> Result<()> {
+        if let (Some(max), Some(configured)) = (self.max_retention_reduction()?, configured) {
+            let reduction = 1.0 - amount as f64 / configured as f64;
+            if reduction > max {
+                bail!(
+                    "policy violation: retention of {} for {} is {:.0}% below the configured {}, the maximum reduction is {:.0}%",
+                    amount,
+                    table,
+                    reduction * 100.0,
+                    configured,
+                    max * 100.0
+                );
+            }
+        }
+
+        if !self.allow_full_truncate() {
+            match get_newest_timestamp(client, table)? {
+                Some(newest) if newest < *cutoff => bail!(
+                    "policy violation: cutoff would drop every partition of {} and allow_full_truncate is false",
+                    table
+                ),
+                _ => {}
+            }
+        }
+        Ok(())
+    }
+}
+
+// Fixed amounts of the tables in the config files, recorded before env vars,
+// --policy and --policy-csv override them, for max_retention_reduction
+static CONFIGURED_AMOUNTS: OnceLock<HashMap<String, i64>> = OnceLock::new();
+
+fn record_configured_amounts(config: &Config) {
+    let amounts = config
+        .tables
+        .iter()
+        .filter(|(_, c)| c.has_fixed_amount())
+        .map(|(name, c)| (name.clone(), c.amount))
+        .collect();
+    CONFIGURED_AMOUNTS
+        .set(amounts)
+        .expect("configured amounts are recorded once");
+}
+
+fn configured_amount(table: &str) -> Option<i64> {
+    CONFIGURED_AMOUNTS.get()?.get(table).copied()
+}
+
+impl Default for Config {
+    fn default() -> Self {
+        Config {
+            version: Some(CONFIG_VERSION),
+            tables: HashMap::new(),
+            conn_str: Some(String::from(
+                "host=localhost user=admin password=quest port=8812",
+            )),
+            host: None,
+            port: None,
+            user: None,
+            password: None,
+            password_file: None,
+            database: None,
+            min_table_size: None,
+            notify_url: None,
+            defaults: HashMap::new(),
+            prefixes: HashMap::new(),
+            by_partition: HashMap::new(),
+            exclude: Vec::new(),
+            floor: None,
+            policy: Policy::default(),
+            timestamp_pattern: None,
+            timestamp_style: None,
+            identifier_quoting: None,
+            clock: None,
+            tables_query: None,
+            on_missing_table: None,
+            connect_timeout_secs: None,
+            detached_root: None,
+            case_insensitive_tables: false,
+            timestamp_fallbacks: Vec::new(),
+            profiles: HashMap::new(),
+            environment: None,
+            warnings_as_errors: false,
+            read_only: false,
+            pause_file: None,
+            resume_file: None,
+            verify_drops_secs: None,
+        }
+    }
+}
+
+// Quotes a connection string value if it contains spaces, quotes or backslashes
+fn quote_conn_value(v: &str) -> String {
+    if !v.is_empty() && !v.contains(|c: char| c.is_whitespace() || c == '\'' || c == '\\') {
+        return v.to_string();
+    }
+    format!("'{}'", v.replace('\\', "\\\\").replace('\'', "\\'"))
+}
+
+impl Config {
+    fn floor(&self) -> Result<Option<Duration>> {
+        let floor = self
+            .floor
+            .as_deref()
+            .map(parse_duration)
+            .transpose()
+            .map_err(|e| anyhow!(e))
+            .context("invalid floor")?;
+        if let Some(f) = floor.filter(|f| Utc::now().checked_sub_signed(*f).is_none()) {
+            bail!(RetentionPeriodError::InvalidFloor(f));
+        }
+        Ok(floor)
+    }
+
+    // Checks that do not need a server, shared by YAML and programmatic configs
+    fn validate(&self) -> Result<()> {
+        if let Some(c) = &self.conn_str {
+            if c.trim().is_empty() {
+                bail!("conn_str is empty");
+            }
+            c.parse::<postgres::Config>()
+                .context("conn_str is not a valid connection string")?;
+        }
+        self.floor()?;
+        self.policy.max_retention_reduction()?;
+        // every bad entry at once, rather than one per attempt
+        let mut errors: Vec<String> = self
+            .tables
+            .iter()
+            .chain(&self.defaults)
+            .chain(&self.prefixes)
+            .chain(&self.by_partition)
+            .filter_map(|(name, t)| t.validate().err().map(|e| format!("  {}: {:#}", name, e)))
+            .collect();
+        if self.prefixes.contains_key("") {
+            errors.push(String::from(
+                "  prefixes: an empty prefix would match every table, use defaults with \"*\"",
+            ));
+        }
+        for key in self.by_partition.keys() {
+            match PartitionBy::from_str(&key.to_uppercase()) {
+                Ok(PartitionBy::None) => errors.push(format!(
+                    "  {}: tables that are not partitioned are skipped",
+                    key
+                )),
+                Ok(..) => {}
+                Err(e) => errors.push(format!("  {}: {}", key, e)),
+            }
+        }
+        if !errors.is_empty() {
+            errors.sort();
+            bail!(
+                "{} invalid table entries:\n{}",
+                errors.len(),
+                errors.join("\n")
+            );
+        }
+        compile_patterns(self.defaults.keys().chain(&self.exclude))?;
+        if let Some(pattern) = &self.timestamp_pattern {
+            pattern.validate()?;
+        }
+        for (name, p) in &self.profiles {
+            if let Some(c) = &p.conn_str {
+                c.parse::<postgres::Config>()
+                    .with_context(|| format!("profile {} has an invalid conn_str", name))?;
+            }
+        }
+        Ok(())
+    }
+
+    // Replaces the connection settings with those of the named profile
+    fn use_profile(&mut self, name: &str) -> Result<()> {
+        let p = match self.profiles.get(name) {
+            Some(p) => p.clone(),
+            None => {
+                let mut known: Vec<&String> = self.profiles.keys().collect();
+                known.sort();
+                bail!(
+                    "unknown profile '{}', the config defines: {}",
+                    name,
+                    if known.is_empty() {
+                        String::from("none")
+                    } else {
+                        known
+                            .iter()
+                            .map(|k| k.as_str())
+                            .collect::<Vec<_>>()
+                            .join(", ")
+                    }
+                );
+            }
+        };
+        self.conn_str = p.conn_str;
+        self.host = p.host;
+        self.port = p.port;
+        self.user = p.user;
+        self.password = p.password;
+        self.password_file = p.password_file;
+        self.database = p.database;
+        if p.environment.is_some() {
+            self.environment = p.environment;
+        }
+        Ok(())
+    }
+
+    fn is_paused(&self) -> bool {
+        self.pause_file
+            .as_ref()
+            .is_some_and(|p| std::path::Path::new(p).exists())
+    }
+
+    fn is_production(&self) -> bool {
+        self.environment
+            .as_deref()
+            .is_some_and(|e| e.trim().eq_ignore_ascii_case("production"))
+    }
+
+    fn has_connection_fields(&self) -> bool {
+        self.host.is_some()
+            || self.port.is_some()
+            || self.user.is_some()
+            || self.password.is_some()
+            || self.password_file.is_some()
+            || self.database.is_some()
+    }
+
+    fn connection_string(&self) -> Result<String> {
+        match (&self.conn_str, self.has_connection_fields()) {
+            (Some(c), false) => Ok(c.clone()),
+            (Some(_), true) => {
+                bail!("conn_str cannot be combined with host/port/user/password/database")
+            }
+            (None, false) => {
+                bail!("either conn_str or host/port/user/password/database must be set")
+            }
+            (None, true) => {
+                if self.password.is_some() && self.password_file.is_some() {
+                    bail!("password and password_file are mutually exclusive");
+                }
+
+                let password = match &self.password_file {
+                    Some(path) => Some(
+                        std::fs::read_to_string(path)
+                            .with_context(|| format!("failed to read password_file {}", path))?
+                            .trim_end()
+                            .to_string(),
+                    ),
+                    None => self.password.clone(),
+                };
+
+                let mut parts = vec![format!(
+                    "host={}",
+                    quote_conn_value(self.host.as_deref().unwrap_or("localhost"))
+                )];
+                parts.push(format!("port={}", self.port.unwrap_or(8812)));
+                if let Some(u) = &self.user {
+                    parts.push(format!("user={}", quote_conn_value(u)));
+                }
+                if let Some(p) = &password {
+                    parts.push(format!("password={}", quote_conn_value(p)));
+                }
+                if let Some(d) = &self.database {
+                    parts.push(format!("dbname={}", quote_conn_value(d)));
+                }
+                Ok(parts.join(" "))
+            }
+        }
+    }
+}
+
+fn pgpass_path() -> Option<PathBuf> {
+    match std::env::var_os("PGPASSFILE") {
+        Some(p) => Some(PathBuf::from(p)),
+        None => std::env::var_os("HOME").map(|h| PathBuf::from(h).join(".pgpass")),
+    }
+}
+
+// Splits a .pgpass line on unescaped colons, resolving \: and \\ escapes
+fn split_pgpass_line(line: &str) -> Vec<String> {
+    let mut fields = vec![String::new()];
+    let mut chars = line.chars();
+    while let Some(c) = chars.next() {
+        match c {
+            '\\' => {
+                if let Some(next) = chars.next() {
+                    fields.last_mut().unwrap().push(next);
+                }
+            }
+            ':' => fields.push(String::new()),
+            _ => fields.last_mut().unwrap().push(c),
+        }
+    }
+    fields
+}
+
+// Finds the password for a connection in the .pgpass file, following libpq:
+// the first matching host:port:database:username:password line wins, `*`
+// matches anything, and files readable by group or others are ignored
+fn pgpass_password(host: &str, port: u16, database: &str, user: &str) -> Result<Option<String>> {
+    let path = match pgpass_path() {
+        Some(p) if p.is_file() => p,
+        _ => return Ok(None),
+    };
+
+    #[cfg(unix)]
+    {
+        use std::os::unix::fs::PermissionsExt;
+        let mode = std::fs::metadata(&path)?.permissions().mode();
+        if mode & 0o077 != 0 {
+            warn!(
+                "password file {} has group or world access, ignoring it; permissions should be u=rw (0600) or less",
+                path.display()
+            );
+            return Ok(None);
+        }
+    }
+
+    let contents = std::fs::read_to_string(&path)
+        .with_context(|| format!("failed to read password file {}", path.display()))?;
+    let port = port.to_string();
+    let wanted = [host, port.as_str(), database, user];
+    for line in contents.lines() {
+        if line.trim().is_empty() || line.starts_with('#') {
+            continue;
+        }
+        let fields = split_pgpass_line(line);
+        if fields.len() != 5 {
+            continue;
+        }
+        if fields[..4]
+            .iter()
+            .zip(wanted)
+            .all(|(f, w)| f == "*" || f == w)
+        {
+            return Ok(Some(fields[4].clone()));
+        }
+    }
+    Ok(None)
+}
+
+// Fills in a missing password from .pgpass, matching on the connection's host, port, database and user
+fn apply_pgpass(conn_str: String) -> Result<String> {
+    let pg: postgres::Config = conn_str.parse().context("invalid connection string")?;
+    if pg.get_password().is_some() {
+        return Ok(conn_str);
+    }
+    let user = match pg.get_user() {
+        Some(u) => u.to_string(),
+        None => return Ok(conn_str),
+    };
+    let host = match pg.get_hosts().first() {
+        Some(Host::Tcp(h)) => h.clone(),
+        _ => String::from("localhost"),
+    };
+    let port = pg.get_ports().first().copied().unwrap_or(5432);
+    let database = pg.get_dbname().unwrap_or(&user).to_string();
+
+    match pgpass_password(&host, port, &database, &user)? {
+        Some(password) => Ok(format!(
+            "{} password={}",
+            conn_str,
+            quote_conn_value(&password)
+        )),
+        None => Ok(conn_str),
+    }
+}
+
+const CONFIG_VERSION: u32 = 5;
+
+// Config schema changes, as (version, breaking, description). Configs without
+// a version field are treated as version 1.
+const CONFIG_CHANGES: &[(u32, bool, &str)] = &[
+    (
+        2,
+        false,
+        "added optional `version` and `min_table_size` fields",
+    ),
+    (
+        3,
+        false,
+        "`policy.always_keep_newest` defaults to true, cutoffs no longer reach into the newest partition",
+    ),
+    (
+        4,
+        false,
+        "cutoffs are written as timestamp literals unless `timestamp_style: to_timestamp` or a `timestamp_pattern` is set",
+    ),
+    (
+        5,
+        false,
+        "MONTH tables are supported, their cutoffs align to the start of a month",
+    ),
+];
+
+fn check_config_version(c: &Config) -> Result<()> {
+    let version = c.version.unwrap_or(1);
+    if c.version.is_none() {
+        warn!(
+            "config has no version field, assuming version 1 (current is {})",
+            CONFIG_VERSION
+        );
+    }
+
+    if version > CONFIG_VERSION {
+        bail!(
+            "config version {} is newer than the supported version {}, upgrade questdb-retention",
+            version,
+            CONFIG_VERSION
+        );
+    }
+
+    let mut breaking = Vec::new();
+    for (v, is_breaking, change) in CONFIG_CHANGES.iter().filter(|(v, ..)| *v > version) {
+        if *is_breaking {
+            breaking.push(format!("  version {}: {}", v, change));
+        } else {
+            warn!("changed in config version {}: {}", v, change);
+        }
+    }
+
+    if !breaking.is_empty() {
+        bail!(
+            "config version {} is incompatible, migrate it to version {}:\n{}",
+            version,
+            CONFIG_VERSION,
+            breaking.join("\n")
+        );
+    }
+    Ok(())
+}
+
+// Layers `overlay` on top of `base`: set values in the overlay win, table maps
+// are merged key-wise and exclude lists are combined. Connection settings are
+// taken as a group so a base conn_str never mixes with overlay host/port fields.
+fn merge_configs(base: Config, overlay: Config) -> Config {
+    let overlay_connects = overlay.conn_str.is_some() || overlay.has_connection_fields();
+    let conn = if overlay_connects { &overlay } else { &base };
+    let conn_str = conn.conn_str.clone();
+    let host = conn.host.clone();
+    let port = conn.port;
+    let user = conn.user.clone();
+    let password = conn.password.clone();
+    let password_file = conn.password_file.clone();
+    let database = conn.database.clone();
+
+    let mut tables = base.tables;
+    tables.extend(overlay.tables);
+    let mut defaults = base.defaults;
+    defaults.extend(overlay.defaults);
+    let mut prefixes = base.prefixes;
+    prefixes.extend(overlay.prefixes);
+    let mut by_partition = base.by_partition;
+    by_partition.extend(overlay.by_partition);
+    let mut profiles = base.profiles;
+    profiles.extend(overlay.profiles);
+    let mut exclude = base.exclude;
+    for e in overlay.exclude {
+        if !exclude.contains(&e) {
+            exclude.push(e);
+        }
+    }
+
+    Config {
+        version: overlay.version.or(base.version),
+        tables,
+        conn_str,
+        host,
+        port,
+        user,
+        password,
+        password_file,
+        database,
+        min_table_size: overlay.min_table_size.or(base.min_table_size),
+        notify_url: overlay.notify_url.or(base.notify_url),
+        defaults,
+        prefixes,
+        by_partition,
+        exclude,
+        floor: overlay.floor.or(base.floor),
+        policy: base.policy.merge(overlay.policy),
+        timestamp_pattern: overlay.timestamp_pattern.or(base.timestamp_pattern),
+        timestamp_style: overlay.timestamp_style.or(base.timestamp_style),
+        identifier_quoting: overlay.identifier_quoting.or(base.identifier_quoting),
+        clock: overlay.clock.or(base.clock),
+        tables_query: overlay.tables_query.or(base.tables_query),
+        on_missing_table: overlay.on_missing_table.or(base.on_missing_table),
+        connect_timeout_secs: overlay.connect_timeout_secs.or(base.connect_timeout_secs),
+        detached_root: overlay.detached_root.or(base.detached_root),
+        case_insensitive_tables: overlay.case_insensitive_tables || base.case_insensitive_tables,
+        timestamp_fallbacks: if overlay.timestamp_fallbacks.is_empty() {
+            base.timestamp_fallbacks
+        } else {
+            overlay.timestamp_fallbacks
+        },
+        profiles,
+        environment: overlay.environment.or(base.environment),
+        warnings_as_errors: overlay.warnings_as_errors || base.warnings_as_errors,
+        read_only: overlay.read_only || base.read_only,
+        pause_file: overlay.pause_file.or(base.pause_file),
+        resume_file: overlay.resume_file.or(base.resume_file),
+        verify_drops_secs: overlay.verify_drops_secs.or(base.verify_drops_secs),
+    }
+}
+
+// QDB_RETENTION_<TABLE> for a table: uppercased, anything but ASCII letters and
+// digits replaced by an underscore, so my-table.v2 becomes QDB_RETENTION_MY_TABLE_V2
+fn retention_env_var(table: &str) -> String {
+    let sanitized: String = table
+        .chars()
+        .map(|c| {
+            if c.is_ascii_alphanumeric() {
+                c.to_ascii_uppercase()
+            } else {
+                '_'
+            }
+        })
+        .collect();
+    format!("QDB_RETENTION_{}", sanitized)
+}
+
+// Applies QDB_RETENTION_<TABLE>=<amount> overrides to the configured tables
+fn apply_env_overrides(config: &mut Config) -> Result<()> {
+    let mut by_var: HashMap<String, Vec<String>> = HashMap::new();
+    for name in config.tables.keys() {
+        by_var
+            .entry(retention_env_var(name))
+            .or_default()
+            .push(name.clone());
+    }
+    for (var, value) in std::env::vars() {
+        if !var.starts_with("QDB_RETENTION_") {
+            continue;
+        }
+        let tables = match by_var.get(&var) {
+            Some(t) => t,
+            None => {
+                warn!("{} does not match a configured table, ignoring it", var);
+                continue;
+            }
+        };
+        let amount = value
+            .trim()
+            .parse::<i64>()
+            .ok()
+            .filter(|a| *a >= 1)
+            .with_context(|| format!("{}={} is not a positive amount", var, value))?;
+        for t in tables {
+            let entry = config.tables.get_mut(t).expect("table is configured");
+            entry.amount = amount;
+            entry.keep_percent = None;
+            entry.cutoff_query = None;
+        }
+    }
+    Ok(())
+}
+
+fn parse_configs(paths: &[String]) -> Result<Config> {
+    let mut configs = paths.iter().map(|p| parse_config(p));
+    let first = configs.next().context("no config file given")??;
+    configs.try_fold(first, |merged, c| Ok(merge_configs(merged, c?)))
+}
+
+fn parse_config(path: &str) -> Result<Config> {
+    let f = File::open(path).with_context(|| format!("failed to open config {}", path))?;
+    let c: Config =
+        serde_yaml::from_reader(f).with_context(|| format!("failed to parse config {}", path))?;
+    check_config_version(&c).with_context(|| format!("invalid config {}", path))?;
+    c.validate()
+        .with_context(|| format!("invalid config {}", path))?;
+    Ok(c)
+}
+
+/// Builds a [`Config`] in code rather than from YAML, to hand to [`run_config`]
+pub struct ConfigBuilder {
+    config: Config,
+}
+
+impl Default for ConfigBuilder {
+    fn default() -> Self {
+        ConfigBuilder::new()
+    }
+}
+
+impl ConfigBuilder {
+    pub fn new() -> Self {
+        ConfigBuilder {
+            config: Config::default(),
+        }
+    }
+
+    pub fn conn_str(mut self, conn_str: &str) -> Self {
+        self.config.conn_str = Some(conn_str.to_string());
+        self
+    }
+
+    pub fn table(mut self, name: &str, amount: i64) -> Self {
+        self.config.tables.insert(
+            name.to_string(),
+            TableConfig {
+                amount,
+                ..Default::default()
+            },
+        );
+        self
+    }
+
+    pub fn default(mut self, pattern: &str, amount: i64) -> Self {
+        self.config.defaults.insert(
+            pattern.to_string(),
+            TableConfig {
+                amount,
+                ..Default::default()
+            },
+        );
+        self
+    }
+
+    pub fn prefix(mut self, prefix: &str, amount: i64) -> Self {
+        self.config.prefixes.insert(
+            prefix.to_string(),
+            TableConfig {
+                amount,
+                ..Default::default()
+            },
+        );
+        self
+    }
+
+    pub fn exclude(mut self, pattern: &str) -> Self {
+        self.config.exclude.push(pattern.to_string());
+        self
+    }
+
+    pub fn build(self) -> Result<Config> {
+        self.config.validate()?;
+        Ok(self.config)
+    }
+}
+
+// Asks before a destructive operation, unless --assume-yes was passed
+fn confirm(message: &str, assume_yes: bool) -> Result<bool> {
+    if assume_yes {
+        return Ok(true);
+    }
+
+    let mut prompt = ConfirmPrompt::new(message).set_initial(false);
+    match block_on(prompt.run()) {
+        Ok(Some(answer)) => Ok(answer),
+        Ok(None) => Ok(false),
+        Err(e) => Err(anyhow!(e.to_string())),
+    }
+}
+
+fn run_interactive(
+    client: &mut Client,
+    cols: &MetadataColumns,
+    ts_fmt: &TimestampFormat,
+    assume_yes: bool,
+    config: &Config,
+    execution: Execution,
+) -> Result<()> {
+    let floor = config.floor()?;
+    let mut session: Vec<TableResult> = Vec::new();
+    let mut planned: Vec<PlannedTable> = Vec::new();
+
+    loop {
+        let mut prompt =
+            TextPrompt::new("which table do you want to truncate? (leave empty to finish)");
+        let t = match block_on(prompt.run()) {
+            Ok(Some(t)) if !t.trim().is_empty() => t.trim().to_string(),
+            Ok(..) => break,
+            Err(e) => bail!(e.to_string()),
+        };
+
+        match plan_interactive_table(client, cols, config, floor, &t) {
+            Ok(plan) => planned.push(plan),
+            Err(e) => {
+                let result = TableResult::failed(&t, &e);
+                println!("{}", result.to_text(ts_fmt));
+                session.push(result);
+            }
+        }
+    }
+
+    if session.is_empty() && planned.is_empty() {
+        bail!("no table supplied... exiting");
+    }
+
+    let mut summary = RunSummary::default();
+    if !planned.is_empty() {
+        // one review of everything selected, so the impact is clear before anything runs
+        println!("review:");
+        for plan in &planned {
+            println!(
+                "  {}: drop {} partitions older than {} (estimated {} rows / {})",
+                plan.table,
+                plan.partitions,
+                ts_fmt.format(&plan.cutoff),
+                plan.rows,
+                format_bytes(plan.bytes)
+            );
+            for line in &plan.timeline {
+                println!("    {}", line);
+            }
+        }
+        let message = format!(
+            "About to drop partitions from tables {} (estimated {} rows / {}). Proceed?",
+            planned
+                .iter()
+                .map(|p| p.table.as_str())
+                .collect::<Vec<&str>>()
+                .join(", "),
+            planned.iter().map(|p| p.rows).sum::<i64>(),
+            format_bytes(planned.iter().map(|p| p.bytes).sum())
+        );
+        // nothing is dropped when only checking, so there is nothing to confirm
+        if execution == Execution::Execute && !confirm(&message, assume_yes)? {
+            bail!("aborted");
+        }
+
+        for plan in planned {
+            if execution == Execution::Execute {
+                println!("Deleting old partitions of {}...", plan.table);
+            }
+            let result = execute_planned_table(client, &plan, execution)
+                .unwrap_or_else(|e| TableResult::failed(&plan.table, &e));
+            println!("{}", result.to_text(ts_fmt));
+            session.push(result);
+        }
+    }
+
+    for result in &session {
+        summary.record(result);
+    }
+    println!("session summary:");
+    for result in &session {
+        println!("  {}", result.to_text(ts_fmt));
+    }
+    println!("{}", summary);
+    Ok(())
+}
+
+// A table selected in interactive mode, with its cutoff and estimated impact
+struct PlannedTable {
+    table: String,
+    timestamp_col: String,
+    amount: i64,
+    cutoff: DateTime<Utc>,
+    partitions: i64,
+    rows: i64,
+    bytes: i64,
+    timeline: Vec<String>,
+}
+
+// Timeline rows shown either side of the cutoff, the rest are folded into a count
+const TIMELINE_CONTEXT_ROWS: usize = 3;
+
+// Lays partitions out by date, one row per day, month or year depending on the
+// partitioning, marking each one dropped (✗) or kept (✓)
+fn render_timeline(
+    ranges: &[PartitionRange],
+    cutoff: &DateTime<Utc>,
+    partition_by: &PartitionBy,
+) -> Vec<String> {
+    let group_format = match partition_by {
+        PartitionBy::Hour => "%Y-%m-%d",
+        PartitionBy::Day => "%Y-%m",
+        _ => "%Y",
+    };
+    let mut groups: Vec<(String, String)> = Vec::new();
+    for r in ranges {
+        let label = r.min.format(group_format).to_string();
+        let mark = if r.max < *cutoff { '✗' } else { '✓' };
+        match groups.last_mut() {
+            Some((l, marks)) if *l == label => marks.push(mark),
+            _ => groups.push((label, mark.to_string())),
+        }
+    }
+
+    // the rows around the first kept partition are the ones worth reading
+    let boundary = groups
+        .iter()
+        .position(|(_, marks)| marks.contains('✓'))
+        .unwrap_or(groups.len());
+    let first = boundary.saturating_sub(TIMELINE_CONTEXT_ROWS);
+    let last = (boundary + TIMELINE_CONTEXT_ROWS).min(groups.len());
+    let count =
+        |rows: &[(String, String)]| rows.iter().map(|(_, m)| m.chars().count()).sum::<usize>();
+
+    let mut lines = Vec::new();
+    if first > 0 {
+        lines.push(format!(
+            "... {} earlier partitions, all dropped",
+            count(&groups[..first])
+        ));
+    }
+    for (label, marks) in &groups[first..last] {
+        lines.push(format!("{:<10} {}", label, marks));
+    }
+    if last < groups.len() {
+        lines.push(format!(
+            "... {} later partitions, all kept",
+            count(&groups[last..])
+        ));
+    }
+    lines
+}
+
+// Prompts for an amount and works out what retention would do to one table
+fn plan_interactive_table(
+    client: &mut Client,
+    cols: &MetadataColumns,
+    config: &Config,
+    floor: Option<Duration>,
+    name: &str,
+) -> Result<PlannedTable> {
+    let query = format!("SELECT * FROM tables() WHERE {}=$1", cols.name);
+    let table = match client.query(&query, &[&name])?.first() {
+        Some(row) => row_to_table(row, cols)?,
+        None => bail!("table not found '{}'", name),
+    };
+    if table.partition_by == PartitionBy::None {
+        bail!(RetentionPeriodError::InvalidPartitionBy(table.partition_by));
+    }
+
+    let timestamp_col =
+        match get_timestamp_col(client, cols, &table.name, &config.timestamp_fallbacks)? {
+            Some(c) => c,
+            None => bail!(RetentionPeriodError::NoDesignatedTimestamp(table.name)),
+        };
+
+    let mut prompt = TextPrompt::new(format!(
+        "how many {}s do you want to retain?",
+        table.partition_by
+    ))
+    .with_validator(|s| -> Result<(), String> {
+        match s.parse::<i32>() {
+            Ok(..) => Ok(()),
+            Err(e) => Err(format!("error: {}", e)),
+        }
+    });
+    let amount = match block_on(prompt.run()) {
+        Ok(Some(a)) => a.parse::<i64>()?,
+        Ok(None) => bail!("You typed nothing"),
+        Err(e) => bail!(e.to_string()),
+    };
+    let p = new_retention_period(amount, table.partition_by.clone())?;
+
+    let cutoff = compute_cutoff(&table.name, reference_now(), p, floor, None)?;
+    let cutoff = config.policy.keep_newest(client, &table.name, cutoff)?;
+    config.policy.check(
+        client,
+        &table.name,
+        &cutoff,
+        amount,
+        configured_amount(&table.name),
+    )?;
+    let (partitions, rows, bytes) = get_droppable_stats(client, &table.name, &cutoff)?;
+    let ranges = list_partition_ranges(client, &table.name)?;
+    let timeline = render_timeline(&ranges, &cutoff, &table.partition_by);
+    Ok(PlannedTable {
+        table: table.name,
+        timestamp_col,
+        amount,
+        cutoff,
+        partitions,
+        rows,
+        bytes,
+        timeline,
+    })
+}
+
+fn execute_planned_table(
+    client: &mut Client,
+    plan: &PlannedTable,
+    execution: Execution,
+) -> Result<TableResult> {
+    let table_config = TableConfig {
+        amount: plan.amount,
+        ..Default::default()
+    };
+    if execution != Execution::Execute {
+        return check_statements(
+            client,
+            &plan.table,
+            plan.timestamp_col.clone(),
+            plan.cutoff,
+            &table_config,
+            execution,
+        );
+    }
+    let outcome = run(
+        client,
+        &plan.table,
+        &plan.timestamp_col,
+        plan.cutoff,
+        &table_config,
+    )?;
+    Ok(TableResult::from_outcome(&plan.table, plan.cutoff, outcome))
+}
+
+// A table selector from `defaults` or `exclude`: a glob, or a regex when prefixed with `re:`
+enum TablePattern {
+    Glob(Pattern),
+    Regex(String, Regex),
+}
+
+impl TablePattern {
+    fn new(p: &str) -> Result<TablePattern> {
+        match p.strip_prefix("re:") {
+            // anchored, so a regex has to match the whole table name like a glob does
+            Some(re) => Regex::new(&format!("^(?:{})$", re))
+                .map(|r| TablePattern::Regex(p.to_string(), r))
+                .with_context(|| format!("invalid regex '{}'", re)),
+            None => Pattern::new(p)
+                .map(TablePattern::Glob)
+                .with_context(|| format!("invalid pattern '{}'", p)),
+        }
+    }
+
+    fn matches(&self, table: &str) -> bool {
+        match self {
+            TablePattern::Glob(g) => g.matches(table),
+            TablePattern::Regex(_, r) => r.is_match(table),
+        }
+    }
+
+    fn as_str(&self) -> &str {
+        match self {
+            TablePattern::Glob(g) => g.as_str(),
+            TablePattern::Regex(source, _) => source,
+        }
+    }
+}
+
+fn compile_patterns<'a, I>(patterns: I) -> Result<Vec<TablePattern>>
+where
+    I: IntoIterator<Item = &'a String>,
+{
+    patterns.into_iter().map(|p| TablePattern::new(p)).collect()
+}
+
+// Set once at startup from tables_query, the tables() function otherwise
+static TABLES_SOURCE: OnceLock<String> = OnceLock::new();
+
+// What tables are enumerated from, usable after FROM
+fn tables_source() -> &'static str {
+    TABLES_SOURCE.get_or_init(|| String::from("tables()"))
+}
+
+// A tables_query must have the columns row_to_table reads, checked without running it
+fn set_tables_query(client: &mut Client, cols: &MetadataColumns, query: &str) -> Result<()> {
+    let statement = client
+        .prepare(query)
+        .with_context(|| format!("invalid tables_query '{}'", query))?;
+    let names: Vec<&str> = statement.columns().iter().map(|c| c.name()).collect();
+    for required in [cols.name, cols.partition_by] {
+        if !names.contains(&required) {
+            bail!(
+                "tables_query must return a '{}' column, got: {}",
+                required,
+                names.join(", ")
+            );
+        }
+    }
+    TABLES_SOURCE
+        .set(format!("({})", query))
+        .expect("tables source is set once");
+    Ok(())
+}
+
+fn list_table_names(client: &mut Client, cols: &MetadataColumns) -> Result<Vec<String>> {
+    let query = format!("SELECT {} FROM {}", cols.name, tables_source());
+    let rows = client.query(&query, &[]).context("failed to list tables")?;
+    Ok(rows.iter().map(|r| r.get(cols.name)).collect())
+}
+
+fn list_tables(
+    client: &mut Client,
+    cols: &MetadataColumns,
+    with_range: bool,
+    ts_fmt: &TimestampFormat,
+) -> Result<()> {
+    let mut names = list_table_names(client, cols)?;
+    names.sort();
+    for name in names {
+        let table = match get_table(client, cols, &name) {
+            Ok(t) => t,
+            Err(e) => {
+                println!("{}: {:#}", name, e);
+                continue;
+            }
+        };
+        if !with_range {
+            println!("{}: partition_by={}", name, table.partition_by);
+            continue;
+        }
+        let range = get_oldest_partition(client, &name)
+            .and_then(|oldest| Ok((oldest, get_newest_timestamp(client, &name)?)));
+        match range {
+            Ok((Some(oldest), Some(newest))) => println!(
+                "{}: partition_by={}, oldest={}, newest={}",
+                name,
+                table.partition_by,
+                ts_fmt.format(&oldest),
+                ts_fmt.format(&newest)
+            ),
+            Ok(..) => println!("{}: partition_by={}, empty", name, table.partition_by),
+            Err(e) => println!(
+                "{}: partition_by={}, range unavailable: {}",
+                name, table.partition_by, e
+            ),
+        }
+    }
+    Ok(())
+}
+
+// Number of literal characters in a glob, more literals means a more specific pattern
+fn pattern_specificity(p: &str) -> usize {
+    match p.strip_prefix("re:") {
+        Some(re) => re
+            .chars()
+            .filter(|c| !".*+?()[]{}|^$\\".contains(*c))
+            .count(),
+        None => p.chars().filter(|c| !"*?[]!".contains(*c)).count(),
+    }
+}
+
+// Resolves the tables to process and their amounts. Excludes always win, then
+// explicit `tables` entries, then the longest matching `prefixes` entry, then the
+// most specific matching `defaults` pattern. Each table is resolved once, even if
+// several rules match it.
+fn resolve_tables(config: &Config, existing: &[String]) -> Result<Vec<(String, TableConfig)>> {
+    let exclude = compile_patterns(&config.exclude)?;
+    let mut default_patterns: Vec<&String> = config.defaults.keys().collect();
+    default_patterns.sort_by(|a, b| {
+        pattern_specificity(b)
+            .cmp(&pattern_specificity(a))
+            .then(a.cmp(b))
+    });
+    let defaults = compile_patterns(default_patterns.iter().copied())?;
+    let excluded = |t: &str| exclude.iter().any(|p| p.matches(t));
+
+    let mut resolved: Vec<(String, TableConfig)> = config
+        .tables
+        .iter()
+        .filter(|(t, _)| !excluded(t))
+        .map(|(t, c)| (t.clone(), c.clone()))
+        .collect();
+
+    for t in existing {
+        if excluded(t) {
+            continue;
+        }
+        let matches: Vec<&String> = defaults
+            .iter()
+            .zip(&default_patterns)
+            .filter(|(p, _)| p.matches(t))
+            .map(|(_, name)| *name)
+            .collect();
+        let patterns = matches
+            .iter()
+            .map(|p| format!("'{}'", p))
+            .collect::<Vec<String>>()
+            .join(", ");
+
+        if let Some(prefix) = longest_prefix(&config.prefixes, t) {
+            let mut prefixes: Vec<&String> = config
+                .prefixes
+                .keys()
+                .filter(|p| t.starts_with(p.as_str()))
+                .collect();
+            prefixes.sort();
+            if prefixes.len() > 1 {
+                let listed = prefixes
+                    .iter()
+                    .map(|p| format!("'{}'", p))
+                    .collect::<Vec<String>>()
+                    .join(", ");
+                warn!(
+                    "table {} matched by prefixes {}, using the longest '{}'",
+                    t, listed, prefix
+                );
+            }
+            if config.tables.contains_key(t) {
+                warn!(
+                    "table {} matched by explicit entry and prefix '{}', using the explicit entry",
+                    t, prefix
+                );
+                continue;
+            }
+            if !matches.is_empty() {
+                warn!(
+                    "table {} matched by prefix '{}' and defaults {}, using the prefix",
+                    t, prefix, patterns
+                );
+            }
+            resolved.push((t.clone(), config.prefixes[prefix].clone()));
+            continue;
+        }
+
+        if matches.is_empty() {
+            continue;
+        }
+        if config.tables.contains_key(t) {
+            warn!(
+                "table {} matched by explicit entry and defaults {}, using the explicit entry",
+                t, patterns
+            );
+            continue;
+        }
+        if matches.len() > 1 {
+            warn!(
+                "table {} matched by defaults {}, using '{}'",
+                t, patterns, matches[0]
+            );
+        }
+        resolved.push((t.clone(), config.defaults[matches[0]].clone()));
+    }
+
+    resolved.sort_by(|a, b| a.0.cmp(&b.0));
+    resolved.dedup_by(|a, b| a.0 == b.0);
+    Ok(resolved)
+}
+
+fn longest_prefix<'a>(
+    prefixes: &'a HashMap<String, TableConfig>,
+    table: &str,
+) -> Option<&'a String> {
+    prefixes
+        .keys()
+        .filter(|p| table.starts_with(p.as_str()))
+        .max_by_key(|p| p.len())
+}
+
+// Adds the tables whose partitioning has a `by_partition` entry, unless excluded
+// or already resolved from `tables`, `prefixes` or `defaults`
+fn resolve_by_partition(
+    client: &mut Client,
+    cols: &MetadataColumns,
+    config: &Config,
+    resolved: &mut Vec<(String, TableConfig)>,
+) -> Result<()> {
+    let exclude = compile_patterns(&config.exclude)?;
+    let query = format!("SELECT * FROM {}", tables_source());
+    let rows = client.query(&query, &[]).context("failed to list tables")?;
+    for row in &rows {
+        let Ok(table) = row_to_table(row, cols) else {
+            continue;
+        };
+        if exclude.iter().any(|p| p.matches(&table.name)) {
+            continue;
+        }
+        let partition_by = table.partition_by.to_string();
+        let mut entries: Vec<(&String, &TableConfig)> = config
+            .by_partition
+            .iter()
+            .filter(|(k, _)| k.eq_ignore_ascii_case(&partition_by))
+            .collect();
+        entries.sort_by(|a, b| a.0.cmp(b.0));
+        let Some(&(key, table_config)) = entries.first() else {
+            continue;
+        };
+        if resolved.iter().any(|(t, _)| *t == table.name) {
+            warn!(
+                "table {} matched by by_partition '{}' and an entry in tables, prefixes or defaults, using that entry",
+                table.name, key
+            );
+            continue;
+        }
+        if entries.len() > 1 {
+            let keys = entries
+                .iter()
+                .map(|(k, _)| format!("'{}'", k))
+                .collect::<Vec<String>>()
+                .join(", ");
+            warn!(
+                "table {} matched by by_partition {}, using '{}'",
+                table.name, keys, key
+            );
+        }
+        resolved.push((table.name, table_config.clone()));
+    }
+    resolved.sort_by(|a, b| a.0.cmp(&b.0));
+    Ok(())
+}
+
+// Existing tables an exclude pattern keeps out that the config would otherwise cover
+fn excluded_tables(config: &Config, existing: &[String]) -> Result<Vec<String>> {
+    let exclude = compile_patterns(&config.exclude)?;
+    let defaults = compile_patterns(config.defaults.keys())?;
+    Ok(existing
+        .iter()
+        .filter(|t| exclude.iter().any(|p| p.matches(t)))
+        .filter(|t| {
+            config.tables.contains_key(*t)
+                || longest_prefix(&config.prefixes, t).is_some()
+                || defaults.iter().any(|p| p.matches(t))
+        })
+        .cloned()
+        .collect())
+}
+
+fn resolve_config_tables(
+    client: &mut Client,
+    cols: &MetadataColumns,
+    config: &Config,
+) -> Result<Vec<(String, TableConfig)>> {
+    let existing = if config.defaults.is_empty()
+        && config.prefixes.is_empty()
+        && !config.case_insensitive_tables
+    {
+        Vec::new()
+    } else {
+        list_table_names(client, cols)?
+    };
+    let mut resolved = resolve_tables(config, &existing)?;
+    if !config.by_partition.is_empty() {
+        resolve_by_partition(client, cols, config, &mut resolved)?;
+    }
+    if !config.case_insensitive_tables {
+        return Ok(resolved);
+    }
+    Ok(match_table_case(resolved, &existing))
+}
+
+// Renames entries that only match an existing table when case is ignored
+fn match_table_case(
+    resolved: Vec<(String, TableConfig)>,
+    existing: &[String],
+) -> Vec<(String, TableConfig)> {
+    let mut by_lower: HashMap<String, Vec<&String>> = HashMap::new();
+    for t in existing {
+        by_lower.entry(t.to_lowercase()).or_default().push(t);
+    }
+
+    let mut matched: Vec<(String, TableConfig)> = resolved
+        .into_iter()
+        .map(|(t, c)| {
+            if existing.contains(&t) {
+                return (t, c);
+            }
+            match by_lower.get(&t.to_lowercase()).map(|m| m.as_slice()) {
+                Some([actual]) => {
+                    warn!("table {} matched {} ignoring case", t, actual);
+                    ((*actual).clone(), c)
+                }
+                Some(several) => {
+                    warn!(
+                        "table {} matches {} ignoring case, leaving it as is",
+                        t,
+                        several
+                            .iter()
+                            .map(|s| s.as_str())
+                            .collect::<Vec<&str>>()
+                            .join(", ")
+                    );
+                    (t, c)
+                }
+                None => (t, c),
+            }
+        })
+        .collect();
+    matched.sort_by(|a, b| a.0.cmp(&b.0));
+    matched.dedup_by(|a, b| a.0 == b.0);
+    matched
+}
+
+// Read-only description of what a run would do to one table
+fn plan_table(
+    client: &mut Client,
+    cols: &MetadataColumns,
+    config: &Config,
+    name: &str,
+    table_config: Option<&TableConfig>,
+    ts_fmt: &TimestampFormat,
+) -> Result<serde_json::Value> {
+    let table = get_table(client, cols, name)?;
+    let mut plan = serde_json::json!({
+        "table": name,
+        "partition_by": table.partition_by.to_string(),
+        "partitions": count_partitions(client, name)?,
+        "size_bytes": get_table_size(client, name)?,
+        "oldest_partition": get_oldest_partition(client, name)?.map(|t| ts_fmt.format(&t)),
+    });
+
+    let table_config = match table_config {
+        Some(c) => c,
+        None => {
+            plan["action"] = "none (no retention configured)".into();
+            return Ok(plan);
+        }
+    };
+    if get_timestamp_col(client, cols, name, &config.timestamp_fallbacks)?.is_none() {
+        plan["action"] = "skip (no designated timestamp)".into();
+        return Ok(plan);
+    }
+
+    let cutoff = table_cutoff(client, &table, table_config, config)?;
+    match (&table_config.cutoff_query, table_config.keep_percent) {
+        (Some(q), _) => plan["cutoff_query"] = q.as_str().into(),
+        (None, Some(pct)) => plan["keep_percent"] = pct.into(),
+        (None, None) => plan["amount"] = table_config.amount.into(),
+    }
+    plan["mode"] = serde_json::to_value(table_config.mode)?;
+    plan["cutoff"] = ts_fmt.format(&cutoff).into();
+    plan["reason"] = table_config.drop_reason(&table.partition_by).into();
+    plan["droppable_partitions"] = count_droppable_partitions(client, name, &cutoff)?.into();
+    plan["droppable"] = list_droppable_ranges(client, name, &cutoff)?
+        .into_iter()
+        .map(|r| r.name)
+        .collect::<Vec<String>>()
+        .into();
+    plan["droppable_bytes"] = get_droppable_size(client, name, &cutoff)?.into();
+    if let Some(columns) = get_symbol_columns(client, name) {
+        plan["symbol_columns"] = columns.len().into();
+        plan["indexed_columns"] = columns
+            .iter()
+            .filter(|(_, i)| *i)
+            .map(|(c, _)| c.clone())
+            .collect::<Vec<String>>()
+            .into();
+    }
+    Ok(plan)
+}
+
+fn export_plan(
+    client: &mut Client,
+    cols: &MetadataColumns,
+    config: &Config,
+    path: &str,
+    ts_fmt: &TimestampFormat,
+    only_actionable: bool,
+) -> Result<()> {
+    let mut names = list_table_names(client, cols)?;
+    names.sort();
+    let resolved: HashMap<String, TableConfig> = resolve_config_tables(client, cols, config)?
+        .into_iter()
+        .collect();
+
+    let mut tables = Vec::new();
+    for name in &names {
+        let plan = plan_table(client, cols, config, name, resolved.get(name), ts_fmt)
+            .unwrap_or_else(|e| serde_json::json!({ "table": name, "error": format!("{:#}", e) }));
+        let actionable = plan.get("error").is_some()
+            || plan["droppable_partitions"].as_i64().is_some_and(|n| n > 0);
+        if only_actionable && !actionable {
+            continue;
+        }
+        tables.push(plan);
+    }
+    let report = serde_json::json!({
+        "generated_at": ts_fmt.format(&Utc::now()),
+        "tables": tables,
+    });
+
+    let json = serde_json::to_vec_pretty(&report)?;
+    write_output_file(path, &json)
+        .with_context(|| format!("failed to write the plan to {}", path))?;
+    println!("wrote the plan for {} tables to {}", tables.len(), path);
+    Ok(())
+}
+
+fn estimate_from_config(
+    client: &mut Client,
+    cols: &MetadataColumns,
+    config: &Config,
+    only_actionable: bool,
+) -> Result<()> {
+    let mut total = 0;
+    for (t, table_config) in resolve_config_tables(client, cols, config)? {
+        let freed = get_table(client, cols, &t).and_then(|table| {
+            let cutoff = table_cutoff(client, &table, &table_config, config)?;
+            get_droppable_size(client, &t, &cutoff)
+        });
+
+        match freed {
+            Ok(0) if only_actionable => {}
+            Ok(n) => {
+                println!("{}: {} ({} bytes)", t, format_bytes(n), n);
+                if let Some(note) = get_symbol_columns(client, &t)
+                    .as_deref()
+                    .and_then(describe_symbol_storage)
+                {
+                    println!("  {}", note);
+                }
+                total += n;
+            }
+            Err(e) => println!("{}: {:#}", t, e),
+        }
+    }
+    println!("total: {} ({} bytes)", format_bytes(total), total);
+    Ok(())
+}
+
+// What DROP and DETACH would each do to the configured tables, without running
+// either: DROP frees the space, DETACH keeps it on disk as .detached directories
+fn compare_modes(
+    client: &mut Client,
+    cols: &MetadataColumns,
+    config: &Config,
+    ts_fmt: &TimestampFormat,
+) -> Result<()> {
+    let detach_to = config
+        .detached_root
+        .as_deref()
+        .unwrap_or("each table's directory");
+    let (mut total_partitions, mut total_bytes) = (0, 0);
+    for (t, table_config) in resolve_config_tables(client, cols, config)? {
+        let stats = get_table(client, cols, &t).and_then(|table| {
+            let cutoff = table_cutoff(client, &table, &table_config, config)?;
+            let (n, rows, size) = get_droppable_stats(client, &t, &cutoff)?;
+            Ok((cutoff, n, rows, size))
+        });
+        let (cutoff, n, rows, size) = match stats {
+            Ok(s) => s,
+            Err(e) => {
+                println!("{}: {:#}", t, e);
+                continue;
+            }
+        };
+        println!(
+            "{}: {} partitions ({} rows, {}) older than {}",
+            t,
+            n,
+            rows,
+            format_bytes(size),
+            ts_fmt.format(&cutoff)
+        );
+        println!("  drop:   removes them, frees {}", format_bytes(size));
+        println!(
+            "  detach: moves them to .detached directories under {}, frees nothing until purged, can be reattached",
+            detach_to
+        );
+        if let Some(max) = table_config.max_drops_per_run.filter(|m| (*m as i64) < n) {
+            println!("  either is capped at {} partitions per run", max);
+        }
+        total_partitions += n;
+        total_bytes += size;
+    }
+    println!(
+        "total: {} partitions, drop frees {}, detach keeps {} on disk",
+        total_partitions,
+        format_bytes(total_bytes),
+        format_bytes(total_bytes)
+    );
+    Ok(())
+}
+
+// Command line options that shape how a config run is reported
+struct RunOptions {
+    timestamp_format: TimestampFormat,
+    output: OutputFormat,
+    explain: bool,
+    check_sql: bool,
+    emit_sql: Option<String>,
+    config_paths: Vec<String>,
+    purge_detached: bool,
+    only_actionable: bool,
+    resume: bool,
+    // when the process started and how long it may run, checked between tables
+    started: Instant,
+    max_runtime: Option<Duration>,
+    // tables processed at once, and the settings the extra connections are opened with
+    concurrency: usize,
+    pg: postgres::Config,
+}
+
+// What run_one does with the generated statement
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum Execution {
+    Execute,
+    // prepare only, the server parses the statement without running it
+    CheckSql,
+    // return the statement for --emit-sql without touching the server
+    EmitSql,
+}
+
+impl RunOptions {
+    fn execution(&self) -> Execution {
+        if self.emit_sql.is_some() {
+            Execution::EmitSql
+        } else if self.check_sql {
+            Execution::CheckSql
+        } else {
+            Execution::Execute
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, clap::ValueEnum)]
+enum ColorChoice {
+    /// Color when writing to a terminal and NO_COLOR is not set
+    Auto,
+    Always,
+    Never,
+}
+
+static COLOR: OnceLock<ColorChoice> = OnceLock::new();
+
+const RED: &str = "31";
+const GREEN: &str = "32";
+const YELLOW: &str = "33";
+
+// An explicit --color wins over NO_COLOR, which wins over terminal detection
+fn use_color(is_terminal: bool) -> bool {
+    match COLOR.get().copied().unwrap_or(ColorChoice::Auto) {
+        ColorChoice::Always => true,
+        ColorChoice::Never => false,
+        ColorChoice::Auto => {
+            is_terminal && std::env::var_os("NO_COLOR").is_none_or(|v| v.is_empty())
+        }
+    }
+}
+
+fn paint(text: &str, color: &str, is_terminal: bool) -> String {
+    if use_color(is_terminal) {
+        format!("\x1b[{}m{}\x1b[0m", color, text)
+    } else {
+        text.to_string()
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, clap::ValueEnum)]
+enum OutputFormat {
+    Text,
+    /// One JSON object per table, written as soon as the table is done
+    Jsonl,
+    /// A header row, then one row per table
+    Csv,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Serialize)]
+#[serde(rename_all = "snake_case")]
+enum TableStatus {
+    Deleted,
+    Detached,
+    Checked,
+    Emitted,
+    UpToDate,
+    Skipped,
+    Failed,
+}
+
+// Why a table was left alone, grouped in the end-of-run summary
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+enum SkipReason {
+    Missing,
+    Resumed,
+    NotPartitioned,
+    Excluded,
+    BelowMinSize,
+    NoTimestamp,
+    GuardNotSatisfied,
+    PartitionInUse,
+    UpToDate,
+}
+
+impl SkipReason {
+    fn label(&self) -> &'static str {
+        match self {
+            SkipReason::Missing => "missing",
+            SkipReason::Resumed => "completed by the interrupted run",
+            SkipReason::NotPartitioned => "not partitioned",
+            SkipReason::Excluded => "excluded",
+            SkipReason::BelowMinSize => "below min_table_size",
+            SkipReason::NoTimestamp => "without a designated timestamp",
+            SkipReason::GuardNotSatisfied => "failing their guard_query",
+            SkipReason::PartitionInUse => "with a partition in use",
+            SkipReason::UpToDate => "up to date",
+        }
+    }
+}
+
+#[derive(Debug, Clone)]
+struct TableResult {
+    table: String,
+    status: TableStatus,
+    cutoff: Option<DateTime<Utc>>,
+    rows_deleted: u64,
+    // skip reason or error chain
+    message: Option<String>,
+    skip_reason: Option<SkipReason>,
+    // metadata queries and statements together, zero when not measured
+    elapsed: std::time::Duration,
+    // the statement that was run, checked or emitted
+    outcome: Option<RunOutcome>,
+}
+
+impl TableResult {
+    fn from_outcome(table: &str, cutoff: DateTime<Utc>, outcome: Option<RunOutcome>) -> Self {
+        TableResult {
+            table: table.to_string(),
+            status: match outcome {
+                Some(..) => TableStatus::Deleted,
+                None => TableStatus::UpToDate,
+            },
+            cutoff: Some(outcome.as_ref().map_or(cutoff, |o| o.cutoff)),
+            rows_deleted: outcome.as_ref().map_or(0, |o| o.rows_deleted),
+            message: None,
+            skip_reason: None,
+            elapsed: std::time::Duration::ZERO,
+            outcome,
+        }
+    }
+
+    fn skipped(table: &str, reason: SkipReason, message: String) -> Self {
+        TableResult {
+            table: table.to_string(),
+            status: TableStatus::Skipped,
+            cutoff: None,
+            rows_deleted: 0,
+            message: Some(message),
+            skip_reason: Some(reason),
+            elapsed: std::time::Duration::ZERO,
+            outcome: None,
+        }
+    }
+
+    fn failed(table: &str, e: &anyhow::Error) -> Self {
+        TableResult {
+            table: table.to_string(),
+            status: TableStatus::Failed,
+            cutoff: None,
+            rows_deleted: 0,
+            message: Some(format!("{:#}", e)),
+            skip_reason: None,
+            elapsed: std::time::Duration::ZERO,
+            outcome: None,
+        }
+    }
+
+    // nothing was, or would be, removed
+    fn is_up_to_date(&self) -> bool {
+        match self.status {
+            TableStatus::UpToDate => true,
+            TableStatus::Deleted | TableStatus::Detached => {
+                self.rows_deleted == 0 && self.partitions_dropped() == 0
+            }
+            _ => false,
+        }
+    }
+
+    fn partitions_dropped(&self) -> u64 {
+        self.outcome.as_ref().map_or(0, |o| o.partitions_dropped)
+    }
+
+    fn to_text(&self, ts_fmt: &TimestampFormat) -> String {
+        let cutoff = self.cutoff.map(|c| ts_fmt.format(&c)).unwrap_or_default();
+        let message = self.message.as_deref().unwrap_or_default();
+        match self.status {
+            TableStatus::Deleted if self.message.is_some() => format!(
+                "{} rows deleted from {} older than {}, dropped {} partitions ({})",
+                self.rows_deleted,
+                self.table,
+                cutoff,
+                self.partitions_dropped(),
+                message
+            ),
+            TableStatus::Deleted => format!(
+                "{} rows deleted from {} older than {}, dropped {} partitions",
+                self.rows_deleted,
+                self.table,
+                cutoff,
+                self.partitions_dropped()
+            ),
+            TableStatus::Detached => format!(
+                "detached {} partitions of {} older than {}: {}",
+                self.partitions_dropped(),
+                self.table,
+                cutoff,
+                message
+            ),
+            TableStatus::Checked => format!("statement for {} parsed: {}", self.table, message),
+            TableStatus::Emitted => format!("statement for {} written: {}", self.table, message),
+            TableStatus::UpToDate => format!(
+                "{} is already up to date, no partitions older than {}",
+                self.table, cutoff
+            ),
+            TableStatus::Skipped => format!("skipping {}: {}", self.table, message),
+            TableStatus::Failed => message.to_string(),
+        }
+    }
+
+    fn to_json(&self, ts_fmt: &TimestampFormat) -> serde_json::Value {
+        serde_json::json!({
+            "table": self.table,
+            "status": self.status,
+            "cutoff": self.cutoff.map(|c| ts_fmt.format(&c)),
+            "rows_deleted": self.rows_deleted,
+            "partitions_dropped": self.partitions_dropped(),
+            "message": self.message,
+            "sql": self.outcome.as_ref().map(|o| &o.sql),
+            "timestamp_col": self.outcome.as_ref().map(|o| &o.timestamp_col),
+            "duration_ms": self.elapsed.as_millis() as u64,
+        })
+    }
+
+    fn to_csv(&self, ts_fmt: &TimestampFormat) -> [String; 6] {
+        let status = serde_json::to_value(self.status)
+            .ok()
+            .and_then(|s| s.as_str().map(String::from))
+            .unwrap_or_default();
+        let error = match self.status {
+            TableStatus::Failed => self.message.clone().unwrap_or_default(),
+            _ => String::new(),
+        };
+        [
+            self.table.clone(),
+            self.cutoff.map(|c| ts_fmt.format(&c)).unwrap_or_default(),
+            self.rows_deleted.to_string(),
+            status,
+            error,
+            self.partitions_dropped().to_string(),
+        ]
+    }
+}
+
+// new columns go at the end so existing consumers keep working
+const CSV_HEADER: [&str; 6] = [
+    "table",
+    "cutoff",
+    "rows_deleted",
+    "status",
+    "error",
+    "partitions_dropped",
+];
+
+// Writes one CSV record to stdout, the csv crate takes care of quoting
+fn write_csv_record<I, T>(record: I)
+where
+    I: IntoIterator<Item = T>,
+    T: AsRef<[u8]>,
+{
+    let mut out = csv::Writer::from_writer(std::io::stdout().lock());
+    // a closed stdout is not worth aborting a retention run over
+    let _ = out.write_record(record);
+    let _ = out.flush();
+}
+
+fn report(opts: &RunOptions, result: &TableResult) {
+    if opts.only_actionable && result.is_up_to_date() {
+        return;
+    }
+    match opts.output {
+        OutputFormat::Text => {
+            let mut text = result.to_text(&opts.timestamp_format);
+            if matches!(result.status, TableStatus::Deleted | TableStatus::Detached)
+                && !result.elapsed.is_zero()
+            {
+                text += &format!(" in {:.2}s", result.elapsed.as_secs_f64());
+            }
+            let tty = std::io::stdout().is_terminal();
+            match result.status {
+                TableStatus::Failed => println!("{}", paint(&text, RED, tty)),
+                TableStatus::Deleted | TableStatus::Detached if !result.is_up_to_date() => {
+                    println!("{}", paint(&text, GREEN, tty))
+                }
+                _ => println!("{}", text),
+            }
+        }
+        OutputFormat::Jsonl => {
+            let mut out = std::io::stdout().lock();
+            // a closed stdout is not worth aborting a retention run over
+            let _ = writeln!(out, "{}", result.to_json(&opts.timestamp_format));
+            let _ = out.flush();
+        }
+        OutputFormat::Csv => write_csv_record(result.to_csv(&opts.timestamp_format)),
+    }
+}
+
+fn is_permission_denied(e: &anyhow::Error) -> bool {
+    e.chain().any(|c| {
+        c.downcast_ref::<postgres::Error>().and_then(|p| p.code())
+            == Some(&SqlState::INSUFFICIENT_PRIVILEGE)
+    })
+}
+
+// Permissions that allow dropping partitions, as reported by SHOW PERMISSIONS
+const ALTER_PERMISSIONS: &[&str] = &["ALL", "DATABASE ADMIN", "ALTER TABLE", "DROP PARTITION"];
+
+// Only QuestDB Enterprise has access control. On open source builds SHOW
+// PERMISSIONS fails and every user may ALTER, so only a definite answer fails.
+fn check_alter_permission(client: &mut Client, conn_str: &str) -> Result<()> {
+    let user = postgres::Config::from_str(conn_str)
+        .ok()
+        .and_then(|c| c.get_user().map(String::from))
+        .unwrap_or_default();
+
+    let rows = match client.query("SHOW PERMISSIONS", &[]) {
+        Ok(rows) => rows,
+        Err(e) if e.code() == Some(&SqlState::INSUFFICIENT_PRIVILEGE) => {
+            bail!("user '{}' lacks ALTER TABLE privileges", user)
+        }
+        Err(..) => return Ok(()),
+    };
+
+    let granted = rows.iter().any(|r| {
+        r.try_get::<_, String>("permission")
+            .map(|p| ALTER_PERMISSIONS.contains(&p.to_uppercase().as_str()))
+            .unwrap_or(false)
+    });
+    if !rows.is_empty() && !granted {
+        bail!("user '{}' lacks ALTER TABLE privileges", user);
+    }
+    Ok(())
+}
+
+// Prints why each table will or will not be touched, without executing anything
+fn explain_config(
+    client: &mut Client,
+    cols: &MetadataColumns,
+    config: &Config,
+    tables: &[(String, TableConfig)],
+    opts: &RunOptions,
+) -> Result<()> {
+    let fmt = &opts.timestamp_format;
+    let exclude = compile_patterns(&config.exclude)?;
+    let defaults = compile_patterns(config.defaults.keys())?;
+    let mut candidates: Vec<String> = config.tables.keys().cloned().collect();
+    if !defaults.is_empty() || !config.prefixes.is_empty() {
+        for t in list_table_names(client, cols)? {
+            let matched = longest_prefix(&config.prefixes, &t).is_some()
+                || defaults.iter().any(|p| p.matches(&t));
+            if matched && !candidates.contains(&t) {
+                candidates.push(t);
+            }
+        }
+    }
+    candidates.sort();
+    for t in &candidates {
+        if let Some(p) = exclude.iter().find(|p| p.matches(t)) {
+            warn!("table {}: action=skip (excluded by '{}')", t, p.as_str());
+        }
+    }
+
+    for (t, table_config) in tables {
+        // the line, and whether the table is already up to date
+        let trace = (|| -> Result<(String, bool)> {
+            let table = get_table(client, cols, t)?;
+            let prefix = format!(
+                "table {}: partition_by={}, {}",
+                t,
+                table.partition_by,
+                table_config.retention_label()
+            );
+            if table.partition_by == PartitionBy::None {
+                return Ok((format!("{}, action=skip (not partitioned)", prefix), false));
+            }
+            if let Some(min) = config.min_table_size {
+                let size = get_table_size(client, t)?;
+                if size < min {
+                    let line = format!(
+                        "{}, size={}, action=skip (below min_table_size {})",
+                        prefix, size, min
+                    );
+                    return Ok((line, false));
+                }
+            }
+            if get_timestamp_col(client, cols, t, &config.timestamp_fallbacks)?.is_none() {
+                let line = format!("{}, action=skip (no designated timestamp)", prefix);
+                return Ok((line, false));
+            }
+
+            let cutoff = table_cutoff(client, &table, table_config, config)?;
+            let oldest = get_oldest_partition(client, t)?;
+            let up_to_date = oldest.is_none_or(|o| o >= cutoff);
+            let action = match (oldest, table_config.mode) {
+                (Some(o), RetentionMode::Rows) if o < cutoff => {
+                    String::from("delete rows older than cutoff")
+                }
+                (Some(o), mode) if o < cutoff => {
+                    let verb = match mode {
+                        RetentionMode::Detach => "detach",
+                        _ => "drop",
+                    };
+                    let ranges = list_droppable_ranges(client, t, &cutoff)?;
+                    let n = ranges.len();
+                    let span = match (ranges.first(), ranges.last()) {
+                        (Some(first), Some(last)) if n > 1 => {
+                            format!(" {}..{}", first.name, last.name)
+                        }
+                        (Some(only), _) => format!(" {}", only.name),
+                        _ => String::new(),
+                    };
+                    let reason = table_config.drop_reason(&table.partition_by);
+                    match table_config.max_drops_per_run {
+                        Some(max) if max < n => format!(
+                            "{} {} of {} partitions{} (max_drops_per_run), {}",
+                            verb, max, n, span, reason
+                        ),
+                        _ => format!("{} {} partitions{}, {}", verb, n, span, reason),
+                    }
+                }
+                _ => String::from("none (up to date)"),
+            };
+            let line = format!(
+                "{}, now={}, cutoff={}, oldest partition={}, action={}",
+                prefix,
+                fmt.format(&reference_now()),
+                fmt.format(&cutoff),
+                oldest.map_or(String::from("none"), |o| fmt.format(&o)),
+                action
+            );
+            Ok((line, up_to_date))
+        })();
+
+        match trace {
+            Ok((_, true)) if opts.only_actionable => {}
+            Ok((line, _)) => eprintln!("{}", line),
+            Err(e) => eprintln!("table {}: action=error ({:#})", t, e),
+        }
+    }
+    Ok(())
+}
+
+// Writes a whole output file at once, gzip-compressed when the path ends in .gz.
+// Written after the run, including an interrupted one, so the stream is always finished.
+fn write_output_file(path: &str, contents: &[u8]) -> Result<()> {
+    let mut f = File::create(path).with_context(|| format!("failed to create {}", path))?;
+    if path.ends_with(".gz") {
+        let mut gz = flate2::write::GzEncoder::new(f, flate2::Compression::default());
+        gz.write_all(contents)?;
+        gz.finish()?.sync_all()?;
+    } else {
+        f.write_all(contents)?;
+        f.sync_all()?;
+    }
+    Ok(())
+}
+
+// Writes the statements of an --emit-sql run as a script a DBA can review and run by hand
+fn write_sql_file(
+    path: &str,
+    opts: &RunOptions,
+    config: &Config,
+    statements: &[(TableResult, TableConfig)],
+) -> Result<()> {
+    let fmt = &opts.timestamp_format;
+    let mut sql = format!(
+        "-- generated by questdb-retention {} at {}\n",
+        env!("CARGO_PKG_VERSION"),
+        fmt.format(&Utc::now())
+    );
+    if !opts.config_paths.is_empty() {
+        sql += &format!("-- config: {}\n", opts.config_paths.join(", "));
+    }
+    if let Some(floor) = &config.floor {
+        sql += &format!("-- floor: {}\n", floor);
+    }
+    sql += &format!("-- {} statements\n", statements.len());
+    for (result, table_config) in statements {
+        sql += &format!(
+            "\n-- {}: {}, mode={:?}, cutoff={}\n{};\n",
+            result.table,
+            table_config.retention_label(),
+            table_config.mode,
+            result.cutoff.map(|c| fmt.format(&c)).unwrap_or_default(),
+            result.message.as_deref().unwrap_or_default()
+        );
+    }
+    write_output_file(path, sql.as_bytes()).with_context(|| format!("failed to write {}", path))?;
+    eprintln!("wrote {} statements to {}", statements.len(), path);
+    Ok(())
+}
+
+// Start and end of the partition a detached directory name such as
+// 2023-01-01.detached or 2023-01-01.12.detached belongs to
+fn detached_partition_range(
+    dir_name: &str,
+    partition_by: &PartitionBy,
+) -> Option<(DateTime<Utc>, DateTime<Utc>)> {
+    let name = dir_name.strip_suffix(".detached")?;
+    let name = name.split('.').next()?;
+    let parse = |s: &str| NaiveDate::parse_from_str(s, "%Y-%m-%d").ok();
+    let next_month = |d: NaiveDate| match d.month() {
+        12 => NaiveDate::from_ymd_opt(d.year() + 1, 1, 1),
+        m => NaiveDate::from_ymd_opt(d.year(), m + 1, 1),
+    };
+    let (start, end) = match partition_by {
+        PartitionBy::Hour => {
+            let start =
+                NaiveDateTime::parse_from_str(&format!("{}:00:00", name), "%Y-%m-%dT%H:%M:%S")
+                    .ok()?;
+            (start, start + Duration::hours(1))
+        }
+        PartitionBy::Day => {
+            let start = parse(name)?.and_hms_opt(0, 0, 0)?;
+            (start, start + Duration::days(1))
+        }
+        PartitionBy::Week => {
+            let (year, week) = name.split_once("-W")?;
+            let start =
+                NaiveDate::from_isoywd_opt(year.parse().ok()?, week.parse().ok()?, Weekday::Mon)?
+                    .and_hms_opt(0, 0, 0)?;
+            (start, start + Duration::weeks(1))
+        }
+        PartitionBy::Month => {
+            let start = parse(&format!("{}-01", name))?;
+            (
+                start.and_hms_opt(0, 0, 0)?,
+                next_month(start)?.and_hms_opt(0, 0, 0)?,
+            )
+        }
+        PartitionBy::Year => {
+            let start = parse(&format!("{}-01-01", name))?;
+            (
+                start.and_hms_opt(0, 0, 0)?,
+                NaiveDate::from_ymd_opt(start.year() + 1, 1, 1)?.and_hms_opt(0, 0, 0)?,
+            )
+        }
+        PartitionBy::None => return None,
+    };
+    Some((Utc.from_utc_datetime(&start), Utc.from_utc_datetime(&end)))
+}
+
+// Lists detached partitions that lie entirely before the cutoff, and removes
+// them when `purge` is set. Table directories may carry a ~<id> suffix.
+fn handle_detached(
+    client: &mut Client,
+    cols: &MetadataColumns,
+    root: &str,
+    table: &str,
+    cutoff: &DateTime<Utc>,
+    purge: bool,
+) -> Result<()> {
+    let t = get_table(client, cols, table)?;
+    let entries = std::fs::read_dir(root).with_context(|| format!("failed to read {}", root))?;
+    for entry in entries {
+        let dir = entry?.path();
+        let dir_name = dir.file_name().and_then(|n| n.to_str()).unwrap_or_default();
+        if dir_name != table && !dir_name.starts_with(&format!("{}~", table)) {
+            continue;
+        }
+        for partition in std::fs::read_dir(&dir)? {
+            let path = partition?.path();
+            let name = path
+                .file_name()
+                .and_then(|n| n.to_str())
+                .unwrap_or_default();
+            match detached_partition_range(name, &t.partition_by) {
+                Some((_, end)) if end <= *cutoff => {}
+                _ => continue,
+            }
+            if purge {
+                check_writable(&format!("remove {}", path.display()))?;
+                std::fs::remove_dir_all(&path)
+                    .with_context(|| format!("failed to remove {}", path.display()))?;
+                eprintln!("{}: removed detached partition {}", table, name);
+            } else {
+                eprintln!(
+                    "{}: detached partition {} is older than the cutoff, pass --purge-detached to remove it",
+                    table, name
+                );
+            }
+        }
+    }
+    Ok(())
+}
+
+// Checks the amount of each explicitly configured table against its partitioning
+// before anything runs, so all bad entries are reported together
+fn check_table_periods(
+    client: &mut Client,
+    cols: &MetadataColumns,
+    config: &Config,
+    tables: &[(String, TableConfig)],
+) -> Result<()> {
+    let mut errors = Vec::new();
+    for (t, table_config) in tables {
+        let explicit = config.tables.contains_key(t)
+            || (config.case_insensitive_tables
+                && config.tables.keys().any(|k| k.eq_ignore_ascii_case(t)));
+        if !explicit {
+            continue;
+        }
+        let checked = get_table(client, cols, t).and_then(|table| {
+            if table_config.has_fixed_amount() {
+                table_config.retention_period(&table.partition_by)?;
+            } else if table.partition_by == PartitionBy::None {
+                bail!(RetentionPeriodError::InvalidPartitionBy(table.partition_by))
+            }
+            Ok(())
+        });
+        if let Err(e) = checked {
+            errors.push(format!("  {}: {:#}", t, e));
+        }
+    }
+    if !errors.is_empty() {
+        bail!(
+            "{} invalid table entries:\n{}",
+            errors.len(),
+            errors.join("\n")
+        );
+    }
+    Ok(())
+}
+
+// Takes configured tables that do not exist out of the run, as on_missing_table says
+fn drop_missing_tables(
+    client: &mut Client,
+    cols: &MetadataColumns,
+    config: &Config,
+    tables: Vec<(String, TableConfig)>,
+    summary: &mut RunSummary,
+) -> Result<Vec<(String, TableConfig)>> {
+    // tables() rather than tables_query, a table outside the latter still exists
+    let query = format!("SELECT {} FROM tables()", cols.name);
+    let rows = client.query(&query, &[]).context("failed to list tables")?;
+    let existing: Vec<String> = rows.iter().map(|r| r.get(cols.name)).collect();
+    let on_missing = config.on_missing_table.unwrap_or_default();
+    let mut kept = Vec::new();
+    for (t, table_config) in tables {
+        if existing.contains(&t) {
+            kept.push((t, table_config));
+            continue;
+        }
+        match on_missing {
+            MissingTable::Error => bail!("table {} does not exist", t),
+            MissingTable::Warn => warn!("table {} does not exist, skipping it", t),
+            MissingTable::Skip => {}
+        }
+        summary.skipped.push(SkipReason::Missing);
+    }
+    Ok(kept)
+}
+
+fn run_from_config(
+    client: &mut Client,
+    cols: &MetadataColumns,
+    config: &Config,
+    opts: &RunOptions,
+) -> Result<RunSummary> {
+    let mut summary = RunSummary::default();
+    let tables = resolve_config_tables(client, cols, config)?;
+    let tables = drop_missing_tables(client, cols, config, tables, &mut summary)?;
+    check_table_periods(client, cols, config, &tables)?;
+    if opts.explain {
+        explain_config(client, cols, config, &tables, opts)?;
+    }
+    let mut completed: Vec<&String> = Vec::new();
+    if !config.exclude.is_empty() {
+        let existing = list_table_names(client, cols)?;
+        for _ in excluded_tables(config, &existing)? {
+            summary.skipped.push(SkipReason::Excluded);
+        }
+    }
+    // only runs that execute leave progress behind
+    let resume_file = config
+        .resume_file
+        .as_deref()
+        .filter(|_| opts.execution() == Execution::Execute);
+    let resumed = match resume_file {
+        Some(path) if opts.resume => read_resume_file(path)?,
+        Some(path) => {
+            clear_resume_file(path)?;
+            Vec::new()
+        }
+        None => Vec::new(),
+    };
+    let mut interrupted = false;
+    let mut timed_out = false;
+    let mut statements = Vec::new();
+    let mut pending: Vec<&(String, TableConfig)> = Vec::new();
+    for entry in &tables {
+        if resumed.contains(&entry.0) {
+            summary.skipped.push(SkipReason::Resumed);
+            completed.push(&entry.0);
+        } else {
+            pending.push(entry);
+        }
+    }
+
+    // bookkeeping for a finished table, on this thread whichever connection ran it
+    let mut finish = |t: &String, table_config: &TableConfig, result: TableResult| {
+        report(opts, &result);
+        summary.record(&result);
+        if matches!(result.status, TableStatus::Skipped | TableStatus::Failed) {
+            // already reported, only counted so --strict sees it
+            WARNINGS.fetch_add(1, Ordering::SeqCst);
+        }
+        if result.status == TableStatus::Emitted {
+            statements.push((result.clone(), table_config.clone()));
+        }
+        // failed tables are retried by a resumed run
+        if let Some(path) = resume_file.filter(|_| result.status != TableStatus::Failed) {
+            if let Err(e) = record_resume_file(path, t) {
+                warn!("failed to record progress in {}: {:#}", path, e);
+            }
+        }
+    };
+
+    if opts.concurrency > 1 {
+        let finished = run_lanes(cols, config, opts, schedule_lanes(&pending), &mut finish)?;
+        completed.extend(finished);
+        let unfinished = completed.len() < tables.len();
+        interrupted = unfinished && INTERRUPTED.load(Ordering::SeqCst);
+        timed_out = unfinished && !interrupted && runtime_exceeded(opts);
+    } else {
+        for (t, table_config) in pending {
+            if INTERRUPTED.load(Ordering::SeqCst) {
+                interrupted = true;
+                break;
+            }
+            if runtime_exceeded(opts) {
+                timed_out = true;
+                break;
+            }
+
+            let result = process_table(client, cols, config, opts, t, table_config)?;
+            finish(t, table_config, result);
+            completed.push(t);
+        }
+    }
+    if interrupted {
+        eprintln!(
+            "interrupted, completed {} of {} tables: [{}]",
+            completed.len(),
+            tables.len(),
+            completed
+                .iter()
+                .map(|s| s.as_str())
+                .collect::<Vec<&str>>()
+                .join(", ")
+        );
+    }
+    // in config order, whichever connection finished first
+    statements.sort_by_key(|(r, _)| tables.iter().position(|(t, _)| *t == r.table));
+    set_current_table(None);
+
+    if let Some(path) = &opts.emit_sql {
+        write_sql_file(path, opts, config, &statements)?;
+    }
+
+    let dry_run = opts.execution() != Execution::Execute;
+    if let Some(url) = config.notify_url.as_ref().filter(|_| !dry_run) {
+        if let Err(e) = notify(url, &summary) {
+            warn!("failed to send notification to {}: {:#}", url, e);
+        }
+    }
+
+    if let Some(skips) = summary.skip_summary() {
+        eprintln!("{}", skips);
+    }
+    if let Some(slowest) = summary.slowest_tables() {
+        eprintln!("{}", slowest);
+    }
+
+    if let Some(path) = resume_file.filter(|_| interrupted || timed_out) {
+        eprintln!(
+            "progress is recorded in {}, pass --resume to continue",
+            path
+        );
+    }
+    if interrupted {
+        bail!("interrupted by user");
+    }
+    if timed_out {
+        bail!(
+            "run exceeded --max-runtime of {}s after {} of {} tables",
+            opts.max_runtime.map_or(0, |m| m.num_seconds()),
+            completed.len(),
+            tables.len()
+        );
+    }
+    if let Some(path) = resume_file {
+        clear_resume_file(path)?;
+    }
+    Ok(summary)
+}
+
+fn runtime_exceeded(opts: &RunOptions) -> bool {
+    opts.max_runtime
+        .is_some_and(|max| opts.started.elapsed() >= max.to_std().unwrap_or_default())
+}
+
+// Applies retention to one table and reports what happened. Only a missing
+// privilege is returned as an error, every other table would fail the same way.
+fn process_table(
+    client: &mut Client,
+    cols: &MetadataColumns,
+    config: &Config,
+    opts: &RunOptions,
+    t: &str,
+    table_config: &TableConfig,
+) -> Result<TableResult> {
+    // under --concurrency, the heartbeat names the table started last
+    set_current_table(Some(t));
+    let table_started = Instant::now();
+    let result = match config.min_table_size {
+        Some(min) => match get_table_size(client, t) {
+            Ok(size) if size < min => Some(TableResult::skipped(
+                t,
+                SkipReason::BelowMinSize,
+                format!("size {} bytes is below min_table_size {}", size, min),
+            )),
+            Ok(..) => None,
+            Err(e) => Some(TableResult::failed(
+                t,
+                &anyhow!(e).context(format!("failed to get the size of {}", t)),
+            )),
+        },
+        None => None,
+    };
+
+    let result = match result {
+        Some(r) => r,
+        None => match run_one(client, cols, t, table_config, config, opts.execution()) {
+            Ok(r) => r,
+            Err(e) if is_permission_denied(&e) => {
+                return Err(e.context(format!(
+                "failed to apply retention to {}, the connected user lacks ALTER TABLE privileges",
+                t
+            )))
+            }
+            // the writer will have moved on by the next run
+            Err(e)
+                if matches!(
+                    e.downcast_ref::<RetentionPeriodError>(),
+                    Some(RetentionPeriodError::PartitionInUse(..))
+                ) =>
+            {
+                warn!("{:#}", e);
+                TableResult::skipped(
+                    t,
+                    SkipReason::PartitionInUse,
+                    String::from("a targeted partition is in use"),
+                )
+            }
+            Err(e) => {
+                TableResult::failed(t, &e.context(format!("failed to apply retention to {}", t)))
+            }
+        },
+    };
+
+    let result = TableResult {
+        elapsed: table_started.elapsed(),
+        ..result
+    };
+    if let (Some(root), Some(cutoff)) = (&config.detached_root, result.cutoff) {
+        if opts.execution() == Execution::Execute {
+            if let Err(e) = handle_detached(client, cols, root, t, &cutoff, opts.purge_detached) {
+                warn!("failed to check detached partitions of {}: {:#}", t, e);
+            }
+        }
+    }
+    Ok(result)
+}
+
+// Tables that must run one after another: each `group` is one lane in config
+// order, every ungrouped table a lane of its own
+fn schedule_lanes<'a>(tables: &[&'a (String, TableConfig)]) -> Vec<Vec<&'a (String, TableConfig)>> {
+    let mut lanes: Vec<Vec<&(String, TableConfig)>> = Vec::new();
+    let mut by_group: HashMap<&str, usize> = HashMap::new();
+    for entry in tables {
+        match entry.1.group.as_deref() {
+            Some(group) => match by_group.get(group) {
+                Some(&lane) => lanes[lane].push(entry),
+                None => {
+                    by_group.insert(group, lanes.len());
+                    lanes.push(vec![entry]);
+                }
+            },
+            None => lanes.push(vec![entry]),
+        }
+    }
+    lanes
+}
+
+// What a --concurrency worker sends back for each table it processed
+type LaneResult<'a> = (&'a (String, TableConfig), Result<TableResult>);
+
+// Runs the lanes on up to --concurrency connections of their own, a lane at a
+// time per connection, and passes each result to `finish` on this thread.
+// Returns the tables that finished; stops handing out tables once interrupted,
+// out of time or a worker hit an error, which is returned.
+fn run_lanes<'a>(
+    cols: &MetadataColumns,
+    config: &Config,
+    opts: &RunOptions,
+    lanes: Vec<Vec<&'a (String, TableConfig)>>,
+    finish: &mut dyn FnMut(&String, &TableConfig, TableResult),
+) -> Result<Vec<&'a String>> {
+    let next_lane = AtomicUsize::new(0);
+    let failed = AtomicBool::new(false);
+    let (tx, rx) = std::sync::mpsc::channel::<LaneResult<'a>>();
+    let workers = opts.concurrency.min(lanes.len());
+    let mut finished = Vec::new();
+    let mut error = None;
+    std::thread::scope(|scope| {
+        for _ in 0..workers {
+            let tx = tx.clone();
+            let (lanes, next_lane, failed) = (&lanes, &next_lane, &failed);
+            scope.spawn(move || {
+                let mut client = match opts.pg.connect(NoTls) {
+                    Ok(c) => c,
+                    Err(e) => {
+                        failed.store(true, Ordering::SeqCst);
+                        let e = anyhow!(e).context("failed to open a --concurrency connection");
+                        if let Some(first) = lanes.first().and_then(|l| l.first()) {
+                            let _ = tx.send((first, Err(e)));
+                        }
+                        return;
+                    }
+                };
+                loop {
+                    let lane = match lanes.get(next_lane.fetch_add(1, Ordering::SeqCst)) {
+                        Some(lane) => lane,
+                        None => return,
+                    };
+                    for entry in lane {
+                        let stop = INTERRUPTED.load(Ordering::SeqCst)
+                            || runtime_exceeded(opts)
+                            || failed.load(Ordering::SeqCst);
+                        if stop {
+                            return;
+                        }
+                        let result =
+                            process_table(&mut client, cols, config, opts, &entry.0, &entry.1);
+                        if result.is_err() {
+                            failed.store(true, Ordering::SeqCst);
+                        }
+                        if tx.send((entry, result)).is_err() {
+                            return;
+                        }
+                    }
+                }
+            });
+        }
+        drop(tx);
+        for (entry, result) in rx {
+            match result {
+                Ok(result) => {
+                    finish(&entry.0, &entry.1, result);
+                    finished.push(&entry.0);
+                }
+                Err(e) => {
+                    error.get_or_insert(e);
+                }
+            }
+        }
+    });
+    match error {
+        Some(e) => Err(e),
+        None => Ok(finished),
+    }
+}
+
+// One completed table per line, appended as the run goes so a crash keeps it
+fn read_resume_file(path: &str) -> Result<Vec<String>> {
+    match std::fs::read_to_string(path) {
+        Ok(s) => Ok(s.lines().map(String::from).collect()),
+        Err(e) if e.kind() == std::io::ErrorKind::NotFound => Ok(Vec::new()),
+        Err(e) => Err(anyhow!(e).context(format!("failed to read {}", path))),
+    }
+}
+
+fn record_resume_file(path: &str, table: &str) -> Result<()> {
+    let mut f = std::fs::OpenOptions::new()
+        .create(true)
+        .append(true)
+        .open(path)?;
+    writeln!(f, "{}", table)?;
+    f.sync_data()?;
+    Ok(())
+}
+
+fn clear_resume_file(path: &str) -> Result<()> {
+    match std::fs::remove_file(path) {
+        Err(e) if e.kind() != std::io::ErrorKind::NotFound => {
+            Err(anyhow!(e).context(format!("failed to remove {}", path)))
+        }
+        _ => Ok(()),
+    }
+}
+
+// Sleeps for `delay`, waking early when interrupted
+fn sleep_interruptibly(delay: Duration) {
+    let until = Instant::now() + delay.to_std().unwrap_or_default();
+    while !INTERRUPTED.load(Ordering::SeqCst) && Instant::now() < until {
+        std::thread::sleep(std::time::Duration::from_millis(200).min(until - Instant::now()));
+    }
+}
+
+#[derive(Debug, Default, Serialize)]
+struct RunSummary {
+    tables_processed: usize,
+    rows_deleted: u64,
+    partitions_dropped: u64,
+    failures: Vec<TableFailure>,
+    #[serde(skip)]
+    skipped: Vec<SkipReason>,
+    #[serde(skip)]
+    timings: Vec<(String, std::time::Duration)>,
+}
+
+// Tables listed in the slowest tables line of a run
+const SLOWEST_TABLES: usize = 3;
+
+#[derive(Debug, Serialize)]
+struct TableFailure {
+    table: String,
+    error: String,
+}
+
+impl RunSummary {
+    fn record(&mut self, result: &TableResult) {
+        if !result.elapsed.is_zero() {
+            self.timings.push((result.table.clone(), result.elapsed));
+        }
+        if let Some(reason) = result.skip_reason {
+            self.skipped.push(reason);
+        } else if result.is_up_to_date() {
+            self.skipped.push(SkipReason::UpToDate);
+        }
+        match result.status {
+            TableStatus::Failed => self.failures.push(TableFailure {
+                table: result.table.clone(),
+                error: result.message.clone().unwrap_or_default(),
+            }),
+            TableStatus::Skipped => {}
+            _ => {
+                self.tables_processed += 1;
+                self.rows_deleted += result.rows_deleted;
+                self.partitions_dropped += result.partitions_dropped();
+            }
+        }
+    }
+}
+
+impl RunSummary {
+    // "slowest tables: trades (12.40s), quotes (3.10s), ..."
+    fn slowest_tables(&self) -> Option<String> {
+        if self.timings.len() < 2 {
+            return None;
+        }
+        let mut timings = self.timings.clone();
+        timings.sort_by_key(|(_, d)| std::cmp::Reverse(*d));
+        let slowest: Vec<String> = timings
+            .iter()
+            .take(SLOWEST_TABLES)
+            .map(|(t, d)| format!("{} ({:.2}s)", t, d.as_secs_f64()))
+            .collect();
+        Some(format!("slowest tables: {}", slowest.join(", ")))
+    }
+
+    // "Skipped 12 tables: 4 not partitioned, 3 excluded, 5 up to date"
+    fn skip_summary(&self) -> Option<String> {
+        if self.skipped.is_empty() {
+            return None;
+        }
+        let mut reasons = self.skipped.clone();
+        reasons.sort();
+        let mut groups: Vec<(SkipReason, usize)> = Vec::new();
+        for reason in reasons {
+            match groups.last_mut() {
+                Some((r, n)) if *r == reason => *n += 1,
+                _ => groups.push((reason, 1)),
+            }
+        }
+        let groups: Vec<String> = groups
+            .iter()
+            .map(|(r, n)| format!("{} {}", n, r.label()))
+            .collect();
+        Some(format!(
+            "Skipped {} tables: {}.",
+            self.skipped.len(),
+            groups.join(", ")
+        ))
+    }
+}
+
+impl fmt::Display for RunSummary {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(
+            f,
+            "questdb-retention: {} tables processed, {} rows deleted, dropped {} partitions, {} failures",
+            self.tables_processed,
+            self.rows_deleted,
+            self.partitions_dropped,
+            self.failures.len()
+        )
+    }
+}
+
+// Slack-compatible payload, the summary fields are included for generic webhooks
+#[cfg(feature = "notify")]
+#[derive(Serialize)]
+struct NotifyPayload<'a> {
+    text: String,
+    #[serde(flatten)]
+    summary: &'a RunSummary,
+}
+
+#[cfg(feature = "notify")]
+fn notify(url: &str, summary: &RunSummary) -> Result<()> {
+    let payload = NotifyPayload {
+        text: summary.to_string(),
+        summary,
+    };
+    reqwest::blocking::Client::new()
+        .post(url)
+        .json(&payload)
+        .send()?
+        .error_for_status()?;
+    Ok(())
+}
+
+#[cfg(not(feature = "notify"))]
+fn notify(_url: &str, _summary: &RunSummary) -> Result<()> {
+    bail!("questdb-retention was built without the 'notify' feature")
+}
+
+// QuestDB accepts any dbname on connect, so confirm the server is really using the requested one
+fn check_database(client: &mut Client, db: &str) -> Result<()> {
+    match client.query_one("SELECT current_database()", &[]) {
+        Ok(row) => {
+            let current: String = row.get(0);
+            if current != db {
+                bail!(
+                    "database '{}' does not exist, the server is using '{}'",
+                    db,
+                    current
+                );
+            }
+        }
+        Err(e) => warn!("could not verify database '{}': {}", db, e),
+    }
+    Ok(())
+}
+
+fn ping(client: &mut Client) -> Result<()> {
+    client
+        .query_one("SELECT 1", &[])
+        .context("connected, but SELECT 1 failed")?;
+    let build: String = client
+        .query_one("SELECT build() build", &[])
+        .context("failed to query the QuestDB version")?
+        .get("build");
+    let tables: i64 = client
+        .query_one("SELECT count() n FROM tables()", &[])
+        .context("failed to list tables")?
+        .get("n");
+    println!("ok: connected to {}, {} tables", build, tables);
+    Ok(())
+}
+
+// Whether the first value of a guard query's first row counts as true
+fn is_truthy(row: Option<&Row>) -> bool {
+    let row = match row {
+        Some(r) if !r.is_empty() => r,
+        _ => return false,
+    };
+    if let Ok(b) = row.try_get::<_, bool>(0) {
+        return b;
+    }
+    if let Ok(n) = row.try_get::<_, i64>(0) {
+        return n != 0;
+    }
+    if let Ok(n) = row.try_get::<_, i32>(0) {
+        return n != 0;
+    }
+    if let Ok(n) = row.try_get::<_, f64>(0) {
+        return n != 0.0;
+    }
+    if let Ok(s) = row.try_get::<_, String>(0) {
+        return !matches!(s.trim().to_lowercase().as_str(), "" | "0" | "false" | "f");
+    }
+    false
+}
+
+// Runs the guard of a table, Ok(false) means retention has to wait
+fn check_guard(client: &mut Client, guard: &str, cutoff: &DateTime<Utc>) -> Result<bool> {
+    let query = guard.replace("{cutoff}", &to_timestamp_sql(cutoff));
+    let rows = client
+        .query(&query, &[])
+        .with_context(|| format!("guard_query failed: '{}'", query))?;
+    Ok(is_truthy(rows.first()))
+}
+
+// The --table path: one table, with a period in explicit units rather than partitions
+fn run_single_table(
+    client: &mut Client,
+    cols: &MetadataColumns,
+    config: &Config,
+    table: &str,
+    p: RetentionPeriod,
+    ts_fmt: &TimestampFormat,
+    execution: Execution,
+) -> Result<()> {
+    let t = get_table(client, cols, table)?;
+    if t.partition_by == PartitionBy::None {
+        bail!(RetentionPeriodError::InvalidPartitionBy(t.partition_by));
+    }
+    let timestamp_col = get_timestamp_col(client, cols, &t.name, &config.timestamp_fallbacks)
+        .context("failed to look up the designated timestamp")?
+        .ok_or_else(|| RetentionPeriodError::NoDesignatedTimestamp(t.name.clone()))?;
+
+    let amount = p.amount;
+    let cutoff = compute_cutoff(&t.name, reference_now(), p, config.floor()?, None)?;
+    let cutoff = config.policy.keep_newest(client, &t.name, cutoff)?;
+    config
+        .policy
+        .check(client, &t.name, &cutoff, amount, configured_amount(&t.name))?;
+    let table_config = TableConfig {
+        amount,
+        ..Default::default()
+    };
+    let result = if execution == Execution::Execute {
+        let outcome = run(client, &t.name, &timestamp_col, cutoff, &table_config)?;
+        TableResult::from_outcome(&t.name, cutoff, outcome)
+    } else {
+        check_statements(
+            client,
+            &t.name,
+            timestamp_col,
+            cutoff,
+            &table_config,
+            execution,
+        )?
+    };
+    println!("{}", result.to_text(ts_fmt));
+    Ok(())
+}
+
+fn get_table(client: &mut Client, cols: &MetadataColumns, table: &str) -> Result<Table> {
+    let query = format!("SELECT * FROM tables() WHERE {}=$1", cols.name);
+    let r = client
+        .query_one(&query, &[&table])
+        .with_context(|| format!("failed to look up table {}", table))?;
+    Ok(row_to_table(&r, cols)?)
+}
+
+// Builds the statements without running them: --emit-sql returns them, the
+// other non-executing modes have the server parse them
+fn check_statements(
+    client: &mut Client,
+    table: &str,
+    timestamp_col: String,
+    cutoff: DateTime<Utc>,
+    table_config: &TableConfig,
+    execution: Execution,
+) -> Result<TableResult> {
+    let statements = retention_statements(client, table, &timestamp_col, cutoff, table_config)?;
+    if statements.is_empty() {
+        return Ok(TableResult::from_outcome(table, cutoff, None));
+    }
+    let query = statements.join(";\n");
+    if execution == Execution::EmitSql {
+        return Ok(TableResult {
+            table: table.to_string(),
+            status: TableStatus::Emitted,
+            cutoff: Some(cutoff),
+            rows_deleted: 0,
+            message: Some(query.clone()),
+            skip_reason: None,
+            elapsed: std::time::Duration::ZERO,
+            outcome: Some(RunOutcome {
+                cutoff,
+                rows_deleted: 0,
+                partitions_dropped: 0,
+                sql: query,
+                timestamp_col,
+            }),
+        });
+    }
+    for statement in &statements {
+        client
+            .prepare(statement)
+            .with_context(|| format!("generated statement failed to parse: '{}'", statement))?;
+    }
+    Ok(TableResult {
+        table: table.to_string(),
+        status: TableStatus::Checked,
+        cutoff: Some(cutoff),
+        rows_deleted: 0,
+        message: Some(query.clone()),
+        skip_reason: None,
+        elapsed: std::time::Duration::ZERO,
+        outcome: Some(RunOutcome {
+            cutoff,
+            rows_deleted: 0,
+            partitions_dropped: 0,
+            sql: query,
+            timestamp_col,
+        }),
+    })
+}
+
+fn run_one(
+    client: &mut Client,
+    cols: &MetadataColumns,
+    table: &str,
+    table_config: &TableConfig,
+    config: &Config,
+    execution: Execution,
+) -> Result<TableResult> {
+    let policy = &config.policy;
+    let t = get_table(client, cols, table)?;
+    // explicitly configured tables were already rejected by check_table_periods
+    if t.partition_by == PartitionBy::None {
+        return Ok(TableResult::skipped(
+            &t.name,
+            SkipReason::NotPartitioned,
+            String::from("not partitioned"),
+        ));
+    }
+    let timestamp_col = match &table_config.timestamp_column {
+        Some(col) => Some(check_timestamp_column(client, &t.name, col)?),
+        None => get_timestamp_col(client, cols, &t.name, &config.timestamp_fallbacks)
+            .context("failed to look up the designated timestamp")?,
+    };
+    let timestamp_col = match timestamp_col {
+        Some(c) => c,
+        None => {
+            return Ok(TableResult::skipped(
+                &t.name,
+                SkipReason::NoTimestamp,
+                String::from("no designated timestamp"),
+            ))
+        }
+    };
+
+    let cutoff = table_cutoff(client, &t, table_config, config)?;
+    policy.check(
+        client,
+        &t.name,
+        &cutoff,
+        table_config.amount,
+        configured_amount(&t.name),
+    )?;
+    if let Some(guard) = &table_config.guard_query {
+        if !check_guard(client, guard, &cutoff)? {
+            warn!("guard_query of {} is not satisfied, skipping it", t.name);
+            return Ok(TableResult::skipped(
+                &t.name,
+                SkipReason::GuardNotSatisfied,
+                String::from("guard_query not satisfied"),
+            ));
+        }
+    }
+
+    // Parse messages are compiled by the server but nothing runs until Execute
+    if execution != Execution::Execute {
+        return check_statements(
+            client,
+            &t.name,
+            timestamp_col,
+            cutoff,
+            table_config,
+            execution,
+        );
+    }
+
+    // names are gone from table_partitions() once detached, so collect them first
+    let detached = match table_config.mode {
+        RetentionMode::Detach => {
+            let mut names = list_droppable_partitions(client, &t.name, &cutoff)
+                .context("failed to list the partitions to detach")?;
+            if let Some(max) = table_config.max_drops_per_run {
+                names.truncate(max);
+            }
+            Some(names)
+        }
+        _ => None,
+    };
+
+    // preserve and where_clause leave some partitions before the cutoff in place,
+    // which verification would flag
+    let verify = config.verify_drops_secs.filter(|_| {
+        table_config.mode != RetentionMode::Rows
+            && table_config.preserve.is_none()
+            && table_config.where_clause.is_none()
+    });
+    let targets = match verify {
+        Some(..) => {
+            let mut names = list_droppable_partitions(client, &t.name, &cutoff)
+                .context("failed to list the partitions to drop")?;
+            if let Some(max) = table_config.max_drops_per_run {
+                names.truncate(max);
+            }
+            names
+        }
+        None => Vec::new(),
+    };
+
+    let mut outcome = run(client, &t.name, &timestamp_col, cutoff, table_config)?;
+    let deleted = outcome.as_ref().map(|o| o.rows_deleted);
+
+    if let (Some(secs), Some(o)) = (verify, outcome.as_mut()) {
+        verify_partitions_gone(client, &t.name, &targets, secs)?;
+        // WAL tables apply the drop asynchronously, after the partitions were counted
+        o.partitions_dropped = o.partitions_dropped.max(targets.len() as u64);
+    }
+
+    // after the fact, but it turns a silent wipe into a failed run
+    if let Some(min) = table_config.min_rows_after.filter(|_| outcome.is_some()) {
+        let remaining = count_rows(client, &t.name).context("failed to count remaining rows")?;
+        if remaining < min {
+            bail!(
+                "{} has {} rows left after retention, below min_rows_after {}",
+                t.name,
+                remaining,
+                min
+            );
+        }
+    }
+
+    let status = match (deleted, &detached) {
+        (Some(..), Some(..)) => TableStatus::Detached,
+        (Some(..), None) => TableStatus::Deleted,
+        (None, _) => TableStatus::UpToDate,
+    };
+
+    // DROP PARTITION does not always report a row count, tell the cases apart
+    let mut message = detached.filter(|_| deleted.is_some()).map(|n| n.join(", "));
+    if deleted == Some(0) && table_config.mode == RetentionMode::Partitions {
+        let dropped = outcome.as_ref().map_or(0, |o| o.partitions_dropped);
+        message = Some(String::from(if dropped > 0 {
+            "count not reported by DROP"
+        } else {
+            "cutoff too recent, no whole partition eligible"
+        }));
+    }
+
+    Ok(TableResult {
+        table: t.name,
+        status,
+        cutoff: Some(cutoff),
+        rows_deleted: deleted.unwrap_or(0),
+        message,
+        skip_reason: None,
+        elapsed: std::time::Duration::ZERO,
+        outcome,
+    })
+}
+
+// Settings whose values are never printed
+const SECRET_CONFIG_KEYS: &[&str] = &["password", "conn_str", "profiles"];
+
+fn describe_value(key: &str, v: &serde_json::Value) -> String {
+    if SECRET_CONFIG_KEYS.contains(&key) {
+        return String::from("<redacted>");
+    }
+    match v {
+        serde_json::Value::String(s) => s.clone(),
+        other => other.to_string(),
+    }
+}
+
+// Field-by-field changes between two JSON objects, as "key: old -> new"
+fn diff_fields(old: &serde_json::Value, new: &serde_json::Value) -> Vec<String> {
+    let empty = serde_json::Map::new();
+    let old = old.as_object().unwrap_or(&empty);
+    let new = new.as_object().unwrap_or(&empty);
+    let mut keys: Vec<&String> = old.keys().chain(new.keys()).collect();
+    keys.sort();
+    keys.dedup();
+    keys.into_iter()
+        .filter_map(|k| {
+            let (o, n) = (
+                old.get(k).unwrap_or(&serde_json::Value::Null),
+                new.get(k).unwrap_or(&serde_json::Value::Null),
+            );
+            (o != n).then(|| {
+                format!(
+                    "{}: {} -> {}",
+                    k,
+                    describe_value(k, o),
+                    describe_value(k, n)
+                )
+            })
+        })
+        .collect()
+}
+
+// "amount=7, mode=partitions", leaving out unset options
+fn describe_table(c: &TableConfig) -> Result<String> {
+    let v = serde_json::to_value(c)?;
+    let fields = v.as_object().into_iter().flatten();
+    Ok(fields
+        .filter(|(_, v)| !v.is_null() && **v != serde_json::Value::Bool(false))
+        .map(|(k, v)| format!("{}={}", k, describe_value(k, v)))
+        .collect::<Vec<_>>()
+        .join(", "))
+}
+
+fn diff_table_maps(
+    section: &str,
+    old: &HashMap<String, TableConfig>,
+    new: &HashMap<String, TableConfig>,
+) -> Result<Vec<String>> {
+    let mut names: Vec<&String> = old.keys().chain(new.keys()).collect();
+    names.sort();
+    names.dedup();
+    let mut lines = Vec::new();
+    for name in names {
+        let line = match (old.get(name), new.get(name)) {
+            (None, Some(n)) => format!("+ {} {}: {}", section, name, describe_table(n)?),
+            (Some(o), None) => format!("- {} {}: {}", section, name, describe_table(o)?),
+            (Some(o), Some(n)) if o != n => format!(
+                "~ {} {}: {}",
+                section,
+                name,
+                diff_fields(&serde_json::to_value(o)?, &serde_json::to_value(n)?).join(", ")
+            ),
+            _ => continue,
+        };
+        lines.push(line);
+    }
+    Ok(lines)
+}
+
+fn diff_configs(old_path: &str, new_path: &str) -> Result<()> {
+    let old = parse_config(old_path)?;
+    let new = parse_config(new_path)?;
+
+    let mut lines = diff_table_maps("table", &old.tables, &new.tables)?;
+    lines.extend(diff_table_maps("default", &old.defaults, &new.defaults)?);
+    lines.extend(diff_table_maps("prefix", &old.prefixes, &new.prefixes)?);
+    lines.extend(diff_table_maps(
+        "by_partition",
+        &old.by_partition,
+        &new.by_partition,
+    )?);
+
+    // every other setting, compared as a whole
+    let strip = |c: &Config| -> Result<serde_json::Value> {
+        let mut v = serde_json::to_value(c)?;
+        if let Some(m) = v.as_object_mut() {
+            m.remove("tables");
+            m.remove("defaults");
+            m.remove("prefixes");
+            m.remove("by_partition");
+        }
+        Ok(v)
+    };
+    lines.extend(
+        diff_fields(&strip(&old)?, &strip(&new)?)
+            .into_iter()
+            .map(|l| format!("~ {}", l)),
+    );
+
+    if lines.is_empty() {
+        println!("no retention changes between {} and {}", old_path, new_path);
+    } else {
+        println!("--- {}\n+++ {}", old_path, new_path);
+        for line in lines {
+            println!("{}", line);
+        }
+    }
+    Ok(())
+}
+
+struct SelftestCase {
+    partition_by: PartitionBy,
+    amount: i64,
+    mode: RetentionMode,
+    where_clause: Option<&'static str>,
+    expected: &'static str,
+}
+
+// Fixed reference time so the generated SQL is reproducible
+const SELFTEST_NOW: &str = "2023-03-15T12:34:56.789012Z";
+
+fn selftest_cases() -> Vec<SelftestCase> {
+    let case = |partition_by, amount, expected| SelftestCase {
+        partition_by,
+        amount,
+        mode: RetentionMode::Partitions,
+        where_clause: None,
+        expected,
+    };
+    vec![
+        case(
+            PartitionBy::Hour,
+            6,
+            "ALTER TABLE trades DROP PARTITION WHERE \"ts\" < '2023-03-15T06:34:56.789012Z'",
+        ),
+        case(
+            PartitionBy::Day,
+            30,
+            "ALTER TABLE trades DROP PARTITION WHERE \"ts\" < '2023-02-13T12:34:56.789012Z'",
+        ),
+        case(
+            PartitionBy::Week,
+            2,
+            "ALTER TABLE trades DROP PARTITION WHERE \"ts\" < '2023-03-01T12:34:56.789012Z'",
+        ),
+        SelftestCase {
+            mode: RetentionMode::Rows,
+            ..case(
+                PartitionBy::Day,
+                1,
+                "DELETE FROM trades WHERE \"ts\" < '2023-03-14T12:34:56.789012Z'",
+            )
+        },
+        SelftestCase {
+            mode: RetentionMode::Detach,
+            ..case(
+                PartitionBy::Hour,
+                48,
+                "ALTER TABLE trades DETACH PARTITION WHERE \"ts\" < '2023-03-13T12:34:56.789012Z'",
+            )
+        },
+        SelftestCase {
+            where_clause: Some("ts > '2020-01-01'"),
+            ..case(
+                PartitionBy::Day,
+                7,
+                "ALTER TABLE trades DROP PARTITION WHERE \"ts\" < '2023-03-08T12:34:56.789012Z' AND (ts > '2020-01-01')",
+            )
+        },
+    ]
+}
+
+fn selftest() -> Result<()> {
+    let now = DateTime::parse_from_rfc3339(SELFTEST_NOW)?.with_timezone(&Utc);
+    let mut failures = 0;
+
+    for c in selftest_cases() {
+        let name = format!("{} x{} {:?}", c.partition_by, c.amount, c.mode);
+        let p = new_retention_period(c.amount, c.partition_by)?;
+        let cutoff = get_oldest_timestamp(now, p)?;
+        if let Some(clause) = c.where_clause {
+            validate_where_clause(clause, "ts", c.mode)?;
+        }
+        let query = retention_query("trades", "ts", cutoff, c.where_clause, c.mode);
+        if query == c.expected {
+            println!("ok   {}: {}", name, query);
+        } else {
+            failures += 1;
+            println!(
+                "FAIL {}:\n  expected: {}\n  got:      {}",
+                name, c.expected, query
+            );
+        }
+    }
+
+    if failures > 0 {
+        bail!("{} selftest check(s) failed", failures);
+    }
+    println!("all selftest checks passed");
+    Ok(())
+}
+
+// The table a config run is working on and since when, for the heartbeat
+static CURRENT_TABLE: Mutex<Option<(String, Instant)>> = Mutex::new(None);
+
+fn set_current_table(table: Option<&str>) {
+    if let Ok(mut current) = CURRENT_TABLE.lock() {
+        *current = table.map(|t| (t.to_string(), Instant::now()));
+    }
+}
+
+// Prints progress every `interval` so a slow run can be told apart from a stuck one
+fn start_heartbeat(interval: Duration, started: Instant) -> Result<()> {
+    let interval = interval
+        .to_std()
+        .ok()
+        .filter(|i| !i.is_zero())
+        .context("--heartbeat must be positive")?;
+    std::thread::spawn(move || loop {
+        std::thread::sleep(interval);
+        let current = CURRENT_TABLE.lock().ok().and_then(|c| c.clone());
+        match current {
+            Some((table, since)) => eprintln!(
+                "heartbeat: {}s elapsed, on {} for {}s",
+                started.elapsed().as_secs(),
+                table,
+                since.elapsed().as_secs()
+            ),
+            None => eprintln!("heartbeat: {}s elapsed", started.elapsed().as_secs()),
+        }
+    });
+    Ok(())
+}
+
+// How long past --max-runtime the watchdog waits, so the check between tables
+// gets to stop the run cleanly and a statement in flight can finish
+const WATCHDOG_GRACE: std::time::Duration = std::time::Duration::from_secs(300);
+
+// Exits the process if it is still running WATCHDOG_GRACE past `max`, for when a
+// statement hangs and the check between tables is never reached
+fn start_watchdog(max: Duration) -> Result<()> {
+    let limit = max
+        .to_std()
+        .ok()
+        .filter(|l| !l.is_zero())
+        .ok_or_else(|| anyhow!("--max-runtime must be positive"))?;
+    std::thread::spawn(move || {
+        std::thread::sleep(limit + WATCHDOG_GRACE);
+        eprintln!(
+            "error: run exceeded --max-runtime of {}s by {}s, aborting",
+            limit.as_secs(),
+            WATCHDOG_GRACE.as_secs()
+        );
+        std::process::exit(124);
+    });
+    Ok(())
+}
+
+/// The command line tool, everything the binary's main does
+pub fn cli_main() -> Result<()> {
+    run_cli(Args::parse(), None)?;
+    check_strict()
+}
+
+/// Runs retention for `config` the way a config file run of the command line
+/// tool would, with default options. Startup settings such as the timestamp
+/// style are process-wide, so call it at most once per process.
+pub fn run_config(config: Config) -> Result<()> {
+    config.validate().context("invalid config")?;
+    run_cli(Args::parse_from(["questdb-retention"]), Some(config))?;
+    check_strict()
+}
+
+// --strict and warnings_as_errors fail the run once it has finished
+fn check_strict() -> Result<()> {
+    let warnings = WARNINGS.load(Ordering::SeqCst);
+    if STRICT.load(Ordering::SeqCst) && warnings > 0 {
+        bail!(
+            "{} warnings or skipped/failed tables, failing because of --strict",
+            warnings
+        );
+    }
+    Ok(())
+}
+
+// `preset` replaces the config files for library callers
+fn run_cli(args: Args, preset: Option<Config>) -> Result<()> {
+    let started = Instant::now();
+    STRICT.store(args.strict, Ordering::SeqCst);
+    COLOR.set(args.color).expect("color choice is set once");
+    DISPLAY_FORMAT
+        .set(args.timestamp_format.clone())
+        .expect("timestamp format is set once");
+    if let Some(max) = args.max_runtime {
+        start_watchdog(max)?;
+    }
+    if let Some(interval) = args.heartbeat {
+        start_heartbeat(interval, started)?;
+    }
+    let kill_switch = kill_switch_active();
+    if kill_switch {
+        let banner = format!(
+            "RETENTION DISABLED: {} is set, statements are only checked, nothing is dropped or deleted",
+            KILL_SWITCH_VAR
+        );
+        eprintln!(
+            "{}",
+            paint(&banner, YELLOW, std::io::stderr().is_terminal())
+        );
+    }
+    if let Some(Command::Selftest) = args.command {
+        return selftest();
+    }
+    if let Some(Command::DiffConfig { old, new }) = &args.command {
+        return diff_configs(old, new);
+    }
+    if let Some(Command::Schema) = args.command {
+        let schema = schemars::schema_for!(Config);
+        println!("{}", serde_json::to_string_pretty(&schema)?);
+        return Ok(());
+    }
+
+    let has_config = preset.is_some() || !args.config_path.is_empty();
+    let mut config = match preset {
+        Some(c) => c,
+        None if !args.config_path.is_empty() => parse_configs(&args.config_path)?,
+        None => Config::default(),
+    };
+    if let Some(profile) = &args.profile {
+        config.use_profile(profile)?;
+    }
+    record_configured_amounts(&config);
+    apply_env_overrides(&mut config)?;
+    let mut policies = args.policies.clone();
+    if let Some(path) = &args.policy_csv {
+        policies.extend(read_policy_csv(path)?);
+    }
+    if config.warnings_as_errors {
+        STRICT.store(true, Ordering::SeqCst);
+    }
+
+    let pattern = config.timestamp_pattern.clone().unwrap_or_default();
+    pattern.validate().context("invalid to_timestamp pattern")?;
+    TIMESTAMP_PATTERN
+        .set(pattern)
+        .expect("timestamp pattern is set once");
+    let style = config
+        .timestamp_style
+        .unwrap_or(match config.timestamp_pattern {
+            Some(..) => TimestampStyle::ToTimestamp,
+            None => TimestampStyle::Literal,
+        });
+    TIMESTAMP_STYLE
+        .set(style)
+        .expect("timestamp style is set once");
+    IDENTIFIER_QUOTING
+        .set(config.identifier_quoting.unwrap_or_default())
+        .expect("identifier quoting is set once");
+    if args.validate_sql_only {
+        return validate_sql_offline(&config);
+    }
+
+    // before connecting, so nothing a report mode runs can change data
+    if is_read_only_session(&args, &config, kill_switch) {
+        READ_ONLY.store(true, Ordering::SeqCst);
+    }
+    let mut conn_str = config.connection_string()?;
+    if let Some(db) = &args.db {
+        // later keys win, so this overrides any dbname already in the string
+        conn_str = format!("{} dbname={}", conn_str, quote_conn_value(db));
+    }
+    let conn_str = apply_pgpass(conn_str)?;
+    let timeout = args.connect_timeout_secs.or(config.connect_timeout_secs);
+    let mut pg: postgres::Config = conn_str.parse().context("invalid connection string")?;
+    if let Some(secs) = timeout {
+        pg.connect_timeout(std::time::Duration::from_secs(secs));
+    }
+    let mut client = pg.connect(NoTls).with_context(|| match timeout {
+        Some(secs) => format!("failed to connect to QuestDB within {}s", secs),
+        None => String::from("failed to connect to QuestDB"),
+    })?;
+    if let Some(db) = &args.db {
+        check_database(&mut client, db)?;
+    }
+    if let Some(Command::Ping) = args.command {
+        return ping(&mut client);
+    }
+    let cols = detect_metadata_columns(&mut client)?;
+    check_table_partitions(&mut client, &cols)?;
+    set_reference_clock(&mut client, config.clock.unwrap_or_default())?;
+    if let Some(query) = &config.tables_query {
+        set_tables_query(&mut client, &cols, query)?;
+    }
+    if args.list {
+        return list_tables(&mut client, &cols, args.with_range, &args.timestamp_format);
+    }
+    if args.use_table_metadata {
+        apply_table_metadata(&mut client, &cols, &mut config)?;
+    }
+    apply_inline_policies(&mut client, &cols, &mut config, &policies)?;
+
+    if let Some(path) = &args.export_plan {
+        return export_plan(
+            &mut client,
+            &cols,
+            &config,
+            path,
+            &args.timestamp_format,
+            args.only_actionable,
+        );
+    }
+
+    if let Some(Command::CompareModes) = args.command {
+        if !has_config {
+            bail!("compare-modes requires a config file");
+        }
+        return compare_modes(&mut client, &cols, &config, &args.timestamp_format);
+    }
+
+    if let Some(Command::Estimate) = args.command {
+        if !has_config {
+            bail!("estimate requires a config file");
+        }
+        return estimate_from_config(&mut client, &cols, &config, args.only_actionable);
+    }
+
+    if args.purge_detached && config.detached_root.is_none() {
+        bail!("--purge-detached requires detached_root in the config");
+    }
+    if args.resume && config.resume_file.is_none() {
+        bail!("--resume requires resume_file in the config");
+    }
+
+    if config.is_paused() {
+        eprintln!("retention paused (pause file present)");
+        return Ok(());
+    }
+
+    // the kill-switch turns a run into --check-sql, which plans and parses but never executes
+    let check_sql = args.check_sql || (kill_switch && args.emit_sql.is_none());
+    let direct = args.table.is_some() || args.interactive;
+    let execution = if direct || args.emit_sql.is_none() {
+        // --table and -i cannot emit SQL, so under the kill-switch they only parse
+        if check_sql || kill_switch {
+            Execution::CheckSql
+        } else {
+            Execution::Execute
+        }
+    } else {
+        Execution::EmitSql
+    };
+    let confirmed = args.confirm_production
+        || std::env::var("CONFIRM_PROD").is_ok_and(|v| v == "1" || v.eq_ignore_ascii_case("true"));
+    if config.is_production() && execution == Execution::Execute && !confirmed {
+        warn!("this config targets a production instance");
+        bail!("refusing to run against production without --confirm-production or CONFIRM_PROD=1");
+    }
+
+    // with --emit-sql someone else runs the statements, possibly as another user
+    if execution != Execution::EmitSql {
+        check_alter_permission(&mut client, &conn_str)?;
+    }
+
+    if let (Some(table), Some(amount), Some(unit)) = (&args.table, args.amount, &args.unit) {
+        return run_single_table(
+            &mut client,
+            &cols,
+            &config,
+            table,
+            new_retention_period(amount, unit.clone())?,
+            &args.timestamp_format,
+            execution,
+        );
+    }
+
+    if args.interactive {
+        return run_interactive(
+            &mut client,
+            &cols,
+            &args.timestamp_format,
+            args.assume_yes,
+            &config,
+            execution,
+        );
+    }
+
+    if has_config || !policies.is_empty() || args.use_table_metadata {
+        ctrlc::set_handler(|| {
+            if INTERRUPTED.swap(true, Ordering::SeqCst) {
+                // second Ctrl-C, stop waiting for the current table
+                std::process::exit(130);
+            }
+            eprintln!("interrupt received, finishing current table...");
+        })
+        .context("failed to install the SIGINT handler")?;
+        let opts = RunOptions {
+            timestamp_format: args.timestamp_format,
+            output: args.output,
+            explain: args.explain,
+            check_sql,
+            emit_sql: args.emit_sql.clone(),
+            config_paths: args.config_path.clone(),
+            purge_detached: args.purge_detached,
+            only_actionable: args.only_actionable,
+            resume: args.resume,
+            started,
+            max_runtime: args.max_runtime,
+            concurrency: usize::from(args.concurrency),
+            pg: pg.clone(),
+        };
+        if opts.output == OutputFormat::Csv {
+            write_csv_record(CSV_HEADER);
+        }
+        for pass in 1..=args.repeat {
+            // the file may have appeared while waiting for this pass
+            if pass > 1 && config.is_paused() {
+                eprintln!("retention paused (pause file present)");
+                break;
+            }
+            let summary = run_from_config(&mut client, &cols, &config, &opts)?;
+            if summary.rows_deleted == 0 && summary.partitions_dropped == 0 {
+                if args.repeat > 1 {
+                    eprintln!("pass {} removed nothing, every table is up to date", pass);
+                }
+                break;
+            }
+            if pass < args.repeat {
+                let delay = args.delay.unwrap_or_else(Duration::zero);
+                eprintln!(
+                    "pass {} of {}: {} partitions / {} rows removed, next pass in {}s",
+                    pass,
+                    args.repeat,
+                    summary.partitions_dropped,
+                    summary.rows_deleted,
+                    delay.num_seconds()
+                );
+                sleep_interruptibly(delay);
+                if INTERRUPTED.load(Ordering::SeqCst) {
+                    bail!("interrupted by user");
+                }
+            }
+        }
+        return Ok(());
+    }
+
+    bail!("must choose interactive mode, pass a config file, give --policy or --use-table-metadata")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn selftest_now() -> DateTime<Utc> {
+        DateTime::parse_from_rfc3339(SELFTEST_NOW)
+            .unwrap()
+            .with_timezone(&Utc)
+    }
+
+    fn format(ts: DateTime<Utc>) -> String {
+        ts.format(CHRONO_TIMESTAMP_PATTERN).to_string()
+    }
+
+    #[test]
+    fn selftest_scenarios_pass() {
+        selftest().unwrap();
+    }
+
+    fn yaml_config(yaml: &str) -> Config {
+        serde_yaml::from_str(yaml).unwrap()
+    }
+
+    #[test]
+    fn merge_overrides_scalars_with_the_later_file() {
+        let merged = merge_configs(
+            yaml_config("min_table_size: 100\nfloor: 1d\nread_only: true"),
+            yaml_config("min_table_size: 200"),
+        );
+        assert_eq!(merged.min_table_size, Some(200));
+        // unset in the overlay, so the base value stays
+        assert_eq!(merged.floor.as_deref(), Some("1d"));
+        assert!(merged.read_only);
+    }
+
+    // Library callers get the same validation as config files, before connecting
+    #[test]
+    fn run_config_rejects_an_invalid_config() {
+        assert!(run_config(yaml_config("conn_str: ' '")).is_err());
+    }
+
+    #[test]
+    fn merge_combines_policies_field_by_field() {
+        let merged = merge_configs(
+            yaml_config("policy:\n  allow_full_truncate: false"),
+            yaml_config("policy:\n  max_retention_reduction: \"50%\""),
+        );
+        assert!(!merged.policy.allow_full_truncate());
+        assert!(merged.policy.always_keep_newest());
+        assert_eq!(merged.policy.max_retention_reduction().unwrap(), Some(0.5));
+    }
+
+    #[test]
+    fn merge_combines_tables_key_by_key() {
+        let merged = merge_configs(
+            yaml_config("tables:\n  trades: 30\n  quotes: 7"),
+            yaml_config("tables:\n  quotes: 14\n  orders: 90"),
+        );
+        let mut amounts: Vec<(&str, i64)> = merged
+            .tables
+            .iter()
+            .map(|(t, c)| (t.as_str(), c.amount))
+            .collect();
+        amounts.sort();
+        assert_eq!(amounts, [("orders", 90), ("quotes", 14), ("trades", 30)]);
+    }
+
+    #[test]
+    fn merge_combines_excludes_without_duplicates() {
+        let merged = merge_configs(
+            yaml_config("exclude: [\"tmp_*\", \"metrics_debug_*\"]"),
+            yaml_config("exclude: [\"metrics_debug_*\", \"scratch\"]"),
+        );
+        assert_eq!(merged.exclude, ["tmp_*", "metrics_debug_*", "scratch"]);
+    }
+
+    // The connection settings come from one file as a group, the last that has any
+    #[test]
+    fn merge_takes_connection_settings_from_the_last_file_with_any() {
+        let base = yaml_config("conn_str: host=base port=8812");
+        let merged = merge_configs(base.clone(), yaml_config("min_table_size: 1"));
+        assert_eq!(merged.conn_str.as_deref(), Some("host=base port=8812"));
+
+        let merged = merge_configs(base, yaml_config("host: overlay\nport: 9000"));
+        assert_eq!(merged.conn_str, None);
+        assert_eq!(merged.host.as_deref(), Some("overlay"));
+        assert_eq!(merged.port, Some(9000));
+
+        let merged = merge_configs(
+            yaml_config("host: base\nuser: admin"),
+            yaml_config("conn_str: host=overlay"),
+        );
+        assert_eq!(merged.conn_str.as_deref(), Some("host=overlay"));
+        assert_eq!(merged.host, None);
+        assert_eq!(merged.user, None);
+    }
+
+    #[test]
+    fn configured_tables_match_case_insensitively() {
+        let existing = vec![String::from("Trades"), String::from("quotes")];
+        let resolved = vec![
+            (String::from("trades"), TableConfig::default()),
+            (String::from("quotes"), TableConfig::default()),
+            (String::from("missing"), TableConfig::default()),
+        ];
+        let matched: Vec<String> = match_table_case(resolved, &existing)
+            .into_iter()
+            .map(|(t, _)| t)
+            .collect();
+        assert_eq!(matched, ["Trades", "missing", "quotes"]);
+    }
+
+    // Detached partition directories map to the end of their partition
+    #[test]
+    fn detached_partitions_end_with_their_partition() {
+        for (name, partition_by, expected) in [
+            (
+                "2023-03-01T05.detached",
+                PartitionBy::Hour,
+                Some("2023-03-01T06:00:00.000000"),
+            ),
+            (
+                "2023-03-01.detached",
+                PartitionBy::Day,
+                Some("2023-03-02T00:00:00.000000"),
+            ),
+            (
+                "2023-W10.detached",
+                PartitionBy::Week,
+                Some("2023-03-13T00:00:00.000000"),
+            ),
+            (
+                "2023-12.5.detached",
+                PartitionBy::Month,
+                Some("2024-01-01T00:00:00.000000"),
+            ),
+            (
+                "2023.detached",
+                PartitionBy::Year,
+                Some("2024-01-01T00:00:00.000000"),
+            ),
+            ("2023-03-01", PartitionBy::Day, None),
+        ] {
+            let got = detached_partition_range(name, &partition_by).map(|(_, end)| format(end));
+            assert_eq!(got.as_deref(), expected, "{}", name);
+        }
+    }
+
+    // Reserved words and quotes in the timestamp column must be quoted
+    #[test]
+    fn timestamp_columns_are_quoted() {
+        for (col, expected) in [
+            ("timestamp", "DELETE FROM trades WHERE \"timestamp\" < "),
+            ("order", "DELETE FROM trades WHERE \"order\" < "),
+            ("we\"ird", "DELETE FROM trades WHERE \"we\"\"ird\" < "),
+        ] {
+            let query = retention_query("trades", col, selftest_now(), None, RetentionMode::Rows);
+            assert!(query.starts_with(expected), "{}", query);
+        }
+    }
+
+    #[test]
+    fn identifier_quoting_styles() {
+        assert_eq!(
+            quote_ident_with("we`ird", IdentifierQuoting::Double),
+            "\"we`ird\""
+        );
+        assert_eq!(
+            quote_ident_with("we`ird", IdentifierQuoting::Backtick),
+            "`we``ird`"
+        );
+        assert_eq!(
+            quote_ident_with("we`ird", IdentifierQuoting::None),
+            "we`ird"
+        );
+    }
+
+    #[test]
+    fn capped_drop_lists_partitions() {
+        let names = [String::from("2023-01-01"), String::from("2023-01-02")];
+        assert_eq!(
+            partition_list_query("trades", &names, RetentionMode::Partitions),
+            "ALTER TABLE trades DROP PARTITION LIST '2023-01-01', '2023-01-02'"
+        );
+    }
+
+    // Reads a generated to_timestamp('...', '...') call back the way QuestDB
+    // would, by translating the QuestDB pattern into a strftime one
+    fn parse_to_timestamp(sql: &str) -> DateTime<Utc> {
+        let args = sql
+            .strip_prefix("to_timestamp('")
+            .and_then(|s| s.strip_suffix("')"))
+            .unwrap();
+        let (literal, pattern) = args.split_once("', '").unwrap();
+        assert_eq!(pattern, QUESTDB_TIMESTAMP_PATTERN);
+        let strftime = [
+            ("yyyy", "%Y"),
+            ("MM", "%m"),
+            ("dd", "%d"),
+            ("HH", "%H"),
+            ("mm", "%M"),
+            ("ss", "%S"),
+            ("SSSUUU", "%6f"),
+        ]
+        .iter()
+        .fold(pattern.to_string(), |p, (token, f)| p.replacen(token, f, 1));
+        Utc.from_utc_datetime(&NaiveDateTime::parse_from_str(literal, &strftime).unwrap())
+    }
+
+    // Both cutoff styles must name the exact instant, down to the microsecond
+    #[test]
+    fn cutoffs_round_trip_with_microsecond_precision() {
+        let pattern = TimestampPattern::default();
+        for ts in [
+            selftest_now(),
+            Utc.with_ymd_and_hms(2023, 1, 1, 0, 0, 0).unwrap(),
+            Utc.with_ymd_and_hms(2023, 1, 1, 0, 0, 0).unwrap() + Duration::microseconds(1),
+            Utc.with_ymd_and_hms(2023, 12, 31, 23, 59, 59).unwrap()
+                + Duration::microseconds(999_999),
+        ] {
+            let sql = format_timestamp_sql(&ts, TimestampStyle::ToTimestamp, &pattern);
+            assert_eq!(parse_to_timestamp(&sql), ts, "{}", sql);
+            // what QuestDB prints for the pattern is exactly the literal we send
+            let literal = render_questdb_pattern(&pattern.questdb, &ts).unwrap();
+            assert!(
+                sql.starts_with(&format!("to_timestamp('{}',", literal)),
+                "{}",
+                sql
+            );
+
+            let sql = format_timestamp_sql(&ts, TimestampStyle::Literal, &pattern);
+            let literal = sql.trim_matches('\'');
+            assert_eq!(
+                DateTime::parse_from_rfc3339(literal).unwrap(),
+                ts,
+                "{}",
+                sql
+            );
+        }
+    }
+
+    // Amounts and floors too large for a timestamp are errors, not panics
+    #[test]
+    fn huge_amounts_and_floors_are_rejected() {
+        for partition_by in [
+            PartitionBy::Hour,
+            PartitionBy::Day,
+            PartitionBy::Week,
+            PartitionBy::Month,
+        ] {
+            let p = new_retention_period(10_000_000_000, partition_by.clone()).unwrap();
+            assert!(
+                get_oldest_timestamp(selftest_now(), p).is_err(),
+                "{}",
+                partition_by
+            );
+        }
+        let config = yaml_config("floor: 100000000d");
+        assert!(config.floor().is_err());
+        let floor = Duration::try_days(100_000_000);
+        assert!(clamp_to_floor("trades", selftest_now(), floor).is_err());
+    }
+
+    // "keep 8 weeks" on a DAY table counts back 56 days, aligned to a day partition
+    #[test]
+    fn unit_overrides_the_table_partitioning() {
+        let config = yaml_config("tables:\n  trades:\n    amount: 8\n    unit: week");
+        let table_config = &config.tables["trades"];
+        table_config.validate().unwrap();
+        let p = table_config.retention_period(&PartitionBy::Day).unwrap();
+        let cutoff = compute_cutoff("trades", selftest_now(), p.clone(), None, None).unwrap();
+        assert_eq!(format(cutoff), "2023-01-18T12:34:56.789012");
+        let aligned =
+            compute_cutoff("trades", selftest_now(), p, None, Some(&PartitionBy::Day)).unwrap();
+        assert_eq!(format(aligned), "2023-01-18T00:00:00.000000");
+        assert_eq!(
+            table_config.drop_reason(&PartitionBy::Day),
+            "older than 8 Weeks counted back from now"
+        );
+
+        let config = yaml_config("tables:\n  trades:\n    amount: 1\n    unit: YEAR");
+        assert!(config.tables["trades"].validate().is_err());
+    }
+
+    #[test]
+    fn grouped_tables_share_a_lane() {
+        let table = |name: &str, group: Option<&str>| {
+            let table_config = TableConfig {
+                amount: 1,
+                group: group.map(String::from),
+                ..Default::default()
+            };
+            (name.to_string(), table_config)
+        };
+        let tables = [
+            table("a", Some("volume")),
+            table("b", None),
+            table("c", Some("volume")),
+            table("d", Some("other")),
+            table("e", None),
+        ];
+        let pending: Vec<&(String, TableConfig)> = tables.iter().collect();
+        let lanes: Vec<Vec<&str>> = schedule_lanes(&pending)
+            .iter()
+            .map(|lane| lane.iter().map(|(t, _)| t.as_str()).collect())
+            .collect();
+        assert_eq!(lanes, [vec!["a", "c"], vec!["b"], vec!["d"], vec!["e"]]);
+
+        let config = yaml_config("tables:\n  trades:\n    amount: 1\n    group: ' '");
+        assert!(config.tables["trades"].validate().is_err());
+    }
+
+    #[test]
+    fn rows_mode_allows_where_clauses_without_the_timestamp() {
+        let clause = "NOT (flagged = true)";
+        assert!(validate_where_clause(clause, "ts", RetentionMode::Rows).is_ok());
+        for mode in [RetentionMode::Partitions, RetentionMode::Detach] {
+            assert!(validate_where_clause(clause, "ts", mode).is_err());
+        }
+        let query = retention_query(
+            "trades",
+            "ts",
+            selftest_now(),
+            Some(clause),
+            RetentionMode::Rows,
+        );
+        assert_eq!(
+            query,
+            "DELETE FROM trades WHERE \"ts\" < '2023-03-15T12:34:56.789012Z' AND (NOT (flagged = true))"
+        );
+    }
+
+    #[test]
+    fn year_retention_is_unsupported() {
+        let p = new_retention_period(1, PartitionBy::Year).unwrap();
+        assert!(get_oldest_timestamp(selftest_now(), p).is_err());
+    }
+
+    // MONTH cutoffs roll back across one or more years onto a month start
+    #[test]
+    fn month_cutoffs_roll_back_to_a_month_start() {
+        for (from, amount, expected) in [
+            (SELFTEST_NOW, 1, "2023-02-01T00:00:00.000000"),
+            (SELFTEST_NOW, 3, "2022-12-01T00:00:00.000000"),
+            (SELFTEST_NOW, 15, "2021-12-01T00:00:00.000000"),
+            (SELFTEST_NOW, 30, "2020-09-01T00:00:00.000000"),
+            ("2024-02-29T23:59:59Z", 3, "2023-11-01T00:00:00.000000"),
+            ("2024-01-01T00:00:00Z", 12, "2023-01-01T00:00:00.000000"),
+            ("2023-12-31T12:00:00Z", 11, "2023-01-01T00:00:00.000000"),
+        ] {
+            let from = DateTime::parse_from_rfc3339(from)
+                .unwrap()
+                .with_timezone(&Utc);
+            let p = new_retention_period(amount, PartitionBy::Month).unwrap();
+            let got = format(get_oldest_timestamp(from, p).unwrap());
+            assert_eq!(got, expected, "MONTH x{} from {}", amount, from);
+        }
+    }
+
+    #[test]
+    fn timestamps_align_to_their_partition() {
+        for (partition_by, expected) in [
+            (PartitionBy::Hour, "2023-03-15T12:00:00.000000"),
+            (PartitionBy::Day, "2023-03-15T00:00:00.000000"),
+            (PartitionBy::Week, "2023-03-13T00:00:00.000000"),
+            (PartitionBy::Month, "2023-03-01T00:00:00.000000"),
+            (PartitionBy::Year, "2023-01-01T00:00:00.000000"),
+            (PartitionBy::None, "2023-03-15T12:34:56.789012"),
+        ] {
+            let got = format(align_to_partition(selftest_now(), &partition_by));
+            assert_eq!(got, expected, "{}", partition_by);
+        }
+    }
+
+    // Table resolution for a config built in code: explicit entries, the longest
+    // prefix, the most specific default pattern, and excludes that win over all
+    #[test]
+    fn table_resolution_precedence() {
+        let config = ConfigBuilder::new()
+            .conn_str("host=localhost port=8812")
+            .table("trades", 30)
+            .default("metrics_*", 7)
+            .default("metrics_cpu_*", 3)
+            .default("re:orders_\\d{4}", 14)
+            .prefix("app1_", 30)
+            .prefix("app1_critical_", 365)
+            .exclude("metrics_debug_*")
+            .build()
+            .unwrap();
+        let existing: Vec<String> = [
+            "trades",
+            "metrics_mem",
+            "metrics_cpu_1",
+            "metrics_debug_1",
+            "orders_2023",
+            "orders_archive",
+            "app1_web",
+            "app1_critical_ledger",
+        ]
+        .iter()
+        .map(|t| t.to_string())
+        .collect();
+        let resolved: Vec<String> = resolve_tables(&config, &existing)
+            .unwrap()
+            .iter()
+            .map(|(t, c)| format!("{}={}", t, c.amount))
+            .collect();
+        assert_eq!(
+            resolved,
+            [
+                "app1_critical_ledger=365",
+                "app1_web=30",
+                "metrics_cpu_1=3",
+                "metrics_mem=7",
+                "orders_2023=14",
+                "trades=30",
+            ]
+        );
+    }
+
+    fn resolved_names(config: &Config, existing: &[&str]) -> Vec<String> {
+        let existing: Vec<String> = existing.iter().map(|t| t.to_string()).collect();
+        resolve_tables(config, &existing)
+            .unwrap()
+            .into_iter()
+            .map(|(t, _)| t)
+            .collect()
+    }
+
+    // Each overlap is resolved the quiet way it always was, but now warns
+    #[test]
+    fn overlapping_prefixes_warn() {
+        let config = ConfigBuilder::new()
+            .table("app1_web", 30)
+            .prefix("app1_", 7)
+            .prefix("app1_critical_", 365)
+            .default("app1_*", 14)
+            .build()
+            .unwrap();
+        let existing: Vec<String> = ["app1_web", "app1_critical_ledger", "app1_api"]
+            .iter()
+            .map(|t| t.to_string())
+            .collect();
+        let before = WARNINGS.load(Ordering::SeqCst);
+        let resolved: Vec<String> = resolve_tables(&config, &existing)
+            .unwrap()
+            .iter()
+            .map(|(t, c)| format!("{}={}", t, c.amount))
+            .collect();
+        assert_eq!(
+            resolved,
+            ["app1_api=7", "app1_critical_ledger=365", "app1_web=30"]
+        );
+        // explicit over prefix, two prefixes, and prefix over defaults twice
+        assert!(WARNINGS.load(Ordering::SeqCst) - before >= 4);
+    }
+
+    // An exclude wins wherever it overlaps a default, a prefix or an explicit entry
+    #[test]
+    fn excludes_win_over_overlapping_includes() {
+        let config = ConfigBuilder::new()
+            .default("metrics_*", 30)
+            .exclude("metrics_debug_*")
+            .build()
+            .unwrap();
+        assert_eq!(
+            resolved_names(
+                &config,
+                &["metrics_cpu", "metrics_debug_1", "metrics_debugger"]
+            ),
+            ["metrics_cpu", "metrics_debugger"]
+        );
+
+        // an exclude broader than the default removes everything it would match
+        let config = ConfigBuilder::new()
+            .default("metrics_debug_*", 7)
+            .exclude("metrics_*")
+            .build()
+            .unwrap();
+        assert!(resolved_names(&config, &["metrics_debug_1", "metrics_cpu"]).is_empty());
+
+        // explicit entries and prefixes are excluded too, also by a regex
+        let config = ConfigBuilder::new()
+            .table("metrics_debug_1", 30)
+            .table("trades", 30)
+            .prefix("app1_", 30)
+            .exclude("metrics_debug_*")
+            .exclude("re:app1_tmp_\\d+")
+            .build()
+            .unwrap();
+        assert_eq!(
+            resolved_names(
+                &config,
+                &[
+                    "metrics_debug_1",
+                    "trades",
+                    "app1_web",
+                    "app1_tmp_1",
+                    "app1_tmp_x"
+                ]
+            ),
+            ["app1_tmp_x", "app1_web", "trades"]
+        );
+    }
+
+    // Error messages with commas and quotes must stay in one CSV field
+    #[test]
+    fn csv_fields_are_escaped() {
+        let result = TableResult::failed("trades", &anyhow!("bad \"x\", see log"));
+        let mut w = csv::Writer::from_writer(Vec::new());
+        w.write_record(result.to_csv(&TimestampFormat::Default))
+            .unwrap();
+        let line = String::from_utf8(w.into_inner().unwrap()).unwrap();
+        assert_eq!(line, "trades,,0,failed,\"bad \"\"x\"\", see log\",0\n");
+    }
+
+    // Skips are grouped by reason, in a fixed order
+    #[test]
+    fn skip_summary_groups_by_reason() {
+        let mut summary = RunSummary::default();
+        summary.skipped.extend([
+            SkipReason::UpToDate,
+            SkipReason::NotPartitioned,
+            SkipReason::Excluded,
+            SkipReason::UpToDate,
+        ]);
+        assert_eq!(
+            summary.skip_summary().as_deref(),
+            Some("Skipped 4 tables: 1 not partitioned, 1 excluded, 2 up to date.")
+        );
+    }
+
+    // The offline lint catches broken fragments but accepts escaped quotes
+    #[test]
+    fn offline_lint() {
+        for (sql, ok) in [
+            (
+                "DELETE FROM t WHERE \"ts\" < '2023-01-01' AND (note = 'it''s')",
+                true,
+            ),
+            (
+                "ALTER TABLE t DROP PARTITION WHERE \"ts\" < '2023-01-01",
+                false,
+            ),
+            ("ALTER TABLE t DROP PARTITION WHERE (\"ts\" < now()", false),
+            (
+                "ALTER TABLE t DROP PARTITION WHERE 1=1; DROP TABLE t",
+                false,
+            ),
+        ] {
+            assert_eq!(sql_syntax_error(sql).is_none(), ok, "{}", sql);
+        }
+    }
+
+    // Every report mode is read-only from the start, whatever the config says
+    #[test]
+    fn report_modes_are_read_only() {
+        let config = Config::default();
+        for argv in [
+            &["--list"][..],
+            &["--export-plan", "plan.json", "--config-path", "c.yaml"],
+            &["--config-path", "c.yaml", "estimate"],
+            &["--config-path", "c.yaml", "compare-modes"],
+        ] {
+            let args = Args::try_parse_from(
+                std::iter::once("questdb-retention").chain(argv.iter().copied()),
+            )
+            .unwrap();
+            assert!(is_read_only_session(&args, &config, false), "{:?}", argv);
+        }
+        let args = Args::try_parse_from(["questdb-retention", "--config-path", "c.yaml"]).unwrap();
+        assert!(!is_read_only_session(&args, &config, false));
+        assert!(is_read_only_session(&args, &config, true));
+    }
+
+    // -i and --table have no statement file to write, they must not run instead
+    #[test]
+    fn emit_sql_is_rejected_for_direct_runs() {
+        for argv in [
+            &["-i", "--emit-sql", "plan.sql"][..],
+            &[
+                "--table",
+                "t",
+                "--amount",
+                "1",
+                "--unit",
+                "DAY",
+                "--emit-sql",
+                "plan.sql",
+            ],
+        ] {
+            let parsed = Args::try_parse_from(
+                std::iter::once("questdb-retention").chain(argv.iter().copied()),
+            );
+            assert!(parsed.is_err(), "{:?}", argv);
+        }
+    }
+
+    #[test]
+    fn unit_is_case_insensitive() {
+        let args = Args::try_parse_from([
+            "questdb-retention",
+            "--table",
+            "trades",
+            "--amount",
+            "3",
+            "--unit",
+            "month",
+        ])
+        .unwrap();
+        assert_eq!(args.unit, Some(PartitionBy::Month));
+    }
+
+    // execute_statement refuses anything once the session is read-only
+    #[test]
+    fn read_only_session_refuses_alter() {
+        READ_ONLY.store(true, Ordering::SeqCst);
+        let refused = check_writable("run 'ALTER TABLE trades DROP PARTITION LIST '2023-01-01''");
+        READ_ONLY.store(false, Ordering::SeqCst);
+        assert!(refused.is_err());
+        assert!(check_writable("run 'ALTER TABLE trades'").is_ok());
+    }
+
+    // A partition is marked dropped when it ends before the cutoff
+    #[test]
+    fn timeline_marks_dropped_partitions() {
+        let day =
+            |d: u32| Utc.with_ymd_and_hms(2023, 1, 1, 0, 0, 0).unwrap() + Duration::days(d as i64);
+        let ranges: Vec<PartitionRange> = (29..33)
+            .map(|d| PartitionRange {
+                name: day(d).format("%Y-%m-%d").to_string(),
+                min: day(d),
+                max: day(d + 1) - Duration::microseconds(1),
+                rows: 1,
+            })
+            .collect();
+        assert_eq!(
+            render_timeline(&ranges, &day(32), &PartitionBy::Day),
+            ["2023-01    ✗✗", "2023-02    ✗✓"]
+        );
+    }
+
+    // Both ways of writing a cutoff into a statement
+    #[test]
+    fn cutoff_styles() {
+        let ts = Utc.with_ymd_and_hms(2023, 1, 1, 0, 0, 0).unwrap();
+        let pattern = TimestampPattern::default();
+        assert_eq!(
+            format_timestamp_sql(&ts, TimestampStyle::Literal, &pattern),
+            "'2023-01-01T00:00:00.000000Z'"
+        );
+        assert_eq!(
+            format_timestamp_sql(&ts, TimestampStyle::ToTimestamp, &pattern),
+            "to_timestamp('2023-01-01T00:00:00.000000', 'yyyy-MM-ddTHH:mm:ss.SSSUUU')"
+        );
+    }
+
+    #[test]
+    fn builtin_timestamp_patterns_are_consistent() {
+        TimestampPattern::default().validate().unwrap();
+    }
+
+    #[test]
+    fn env_override_names() {
+        assert_eq!(
+            retention_env_var("my-table.v2"),
+            "QDB_RETENTION_MY_TABLE_V2"
+        );
+    }
+
+    // A profile replaces the whole connection group, an unknown one is an error
+    #[test]
+    fn profile_selection() {
+        let mut config = ConfigBuilder::new()
+            .conn_str("host=localhost port=8812")
+            .build()
+            .unwrap();
+        config.profiles.insert(
+            String::from("staging"),
+            ConnectionProfile {
+                host: Some(String::from("staging")),
+                ..Default::default()
+            },
+        );
+        assert!(config.clone().use_profile("prod").is_err());
+        config.use_profile("staging").unwrap();
+        assert!(config
+            .connection_string()
+            .unwrap()
+            .starts_with("host=staging"));
+    }
+}